@@ -1,29 +1,39 @@
 // src/bin/snm_server.rs
-use actix_web::{web, App, HttpServer, HttpResponse, Result, middleware::Logger, HttpRequest};
+use actix_web::{web, App, HttpServer, middleware::Logger};
 use std::env;
 use dotenv::dotenv;
 
-use snm_brightdata_client::server::{AppState, Config, BrightDataUrls, handle_mcp_request, health_check, cors_handler};
+use snm_brightdata_client::server::{AppState, Config, handle_mcp_request, health_check, metrics, usage_report};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     env_logger::init();
 
-    let config = Config::from_env().expect("Missing config");
-    let state = web::Data::new(AppState::new(config.clone()));
+    let config = Config::from_env()?;
+    // Without keys anyone who reaches the port spends credits, so only
+    // listen locally.
+    let host = if config.api_keys.is_empty() {
+        log::warn!("No API_KEYS configured, /mcp accepts unauthenticated calls on 127.0.0.1 only and /usage is disabled");
+        "127.0.0.1"
+    } else {
+        "0.0.0.0"
+    };
+    let state = web::Data::new(AppState::new(config.clone())?);
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-    let bind_address = format!("0.0.0.0:{}", port);
+    let bind_address = format!("{}:{}", host, port);
 
     println!("🚀 BrightData MCP HTTP Server running on http://{}", bind_address);
 
     HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
+            .wrap(config.cors.middleware())
             .wrap(Logger::default())
             .route("/mcp", web::post().to(handle_mcp_request))
             .route("/health", web::get().to(health_check))
-            .default_service(web::to(cors_handler))
+            .route("/metrics", web::get().to(metrics))
+            .route("/usage", web::get().to(usage_report))
     })
     .bind(&bind_address)?
     .run()
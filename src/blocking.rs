@@ -0,0 +1,104 @@
+// src/blocking.rs
+//! Synchronous counterpart of [`crate::client::BrightDataClient`], enabled with
+//! the `blocking` feature. It must not be used from inside an async runtime.
+
+use crate::breaker::{self, CircuitBreakers};
+use crate::limits::{self, ConcurrencyLimiter};
+use crate::request::{self, SearchRequest};
+use crate::retry::{self, Retried, RetryPolicy};
+use crate::{config::BrightDataConfig, error::BrightDataError};
+use reqwest::blocking::{Client, Response};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct BrightDataClient {
+    config: BrightDataConfig,
+    client: Client,
+    retry: RetryPolicy,
+    breakers: Arc<CircuitBreakers>,
+    limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl BrightDataClient {
+    pub fn new(config: BrightDataConfig) -> Self {
+        Self::with_http_client(config, crate::http::shared_blocking())
+    }
+
+    pub fn with_http_client(config: BrightDataConfig, client: Client) -> Self {
+        Self {
+            config,
+            client,
+            retry: RetryPolicy::from_env(),
+            breakers: breaker::global(),
+            limiter: limits::global(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn from_profile(name: &str) -> Result<Self, BrightDataError> {
+        Ok(Self::new(crate::profile::load(Some(name))?))
+    }
+
+    pub fn config(&self) -> &BrightDataConfig {
+        &self.config
+    }
+
+    pub fn get(&self, target_url: &str) -> Result<Value, BrightDataError> {
+        let payload = serde_json::json!({
+            "url": target_url,
+            "zone": self.config.web_unlocker_zone,
+        });
+
+        let res = self.post("get", &payload)?;
+        Ok(serde_json::from_str(&res.value)?)
+    }
+
+    pub fn search(&self, search: &SearchRequest) -> Result<Value, BrightDataError> {
+        let res = self.post("search", &request::search_payload(&self.config, search))?;
+        Ok(json!({ "raw": res.value, "attempts": res.attempts }))
+    }
+
+    pub fn scrape(&self, url: &str) -> Result<Value, BrightDataError> {
+        let res = self.post("scrape", &request::scrape_payload(&self.config, url, true))?;
+        Ok(json!({ "content": res.value, "attempts": res.attempts }))
+    }
+
+    pub fn extract(&self, url: &str) -> Result<Value, BrightDataError> {
+        let res = self.post("extract", &request::scrape_payload(&self.config, url, false))?;
+        Ok(json!({ "content": res.value, "attempts": res.attempts }))
+    }
+
+    fn post(&self, label: &str, payload: &Value) -> Result<Retried<String>, BrightDataError> {
+        let zone = payload["zone"].as_str().unwrap_or_default();
+        let key = breaker::key(zone, &self.config.endpoint);
+        let _permit = self
+            .limiter
+            .acquire_blocking(zone, payload["url"].as_str().unwrap_or_default(), self.retry.deadline)?;
+        let res = self.send(&key, label, payload)?;
+
+        let status = res.value.status();
+        let headers = res.value.headers().clone();
+        let text = request::check_response(status, &headers, res.value.text()?, payload)?;
+        Ok(Retried { value: text, attempts: res.attempts })
+    }
+
+    fn send(&self, key: &str, label: &str, payload: &Value) -> Result<Retried<Response>, BrightDataError> {
+        self.breakers.acquire(key)?;
+        let res = retry::send_blocking(&self.retry, label, || {
+            self.client
+                .post(&self.config.endpoint)
+                .header("Authorization", request::authorization(&self.config))
+                .json(payload)
+        });
+        match &res {
+            Ok(r) => self.breakers.record_response(key, r.value.status(), r.value.headers()),
+            Err(e) => self.breakers.record_error(key, e),
+        }
+        res
+    }
+}
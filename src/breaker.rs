@@ -0,0 +1,277 @@
+// src/breaker.rs
+//! Circuit breakers keyed by zone and upstream endpoint.
+//!
+//! After `failure_threshold` consecutive failures a breaker opens and calls
+//! fail fast with [`BrightDataError::CircuitOpen`]. Once `open_duration` has
+//! passed a single probe is let through (half-open); its outcome closes the
+//! breaker again or re-opens it.
+
+use crate::error::BrightDataError;
+use crate::retry::{Retried, BRD_ERROR_HEADERS};
+use reqwest::{Response, StatusCode};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static GLOBAL: OnceLock<Arc<CircuitBreakers>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// Numeric encoding used for the metrics gauge.
+    pub fn as_gauge(self) -> u8 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakerConfig {
+    pub failure_threshold: u32,
+    pub open_duration: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl BreakerConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            failure_threshold: env::var("BREAKER_FAILURE_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.failure_threshold),
+            open_duration: env::var("BREAKER_OPEN_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.open_duration),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_started: Option<Instant>,
+}
+
+impl Breaker {
+    fn new() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            probe_started: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerStatus {
+    pub key: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    config: BreakerConfig,
+    breakers: Mutex<HashMap<String, Breaker>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Let a call through, or fail fast while the breaker for `key` is open.
+    pub fn acquire(&self, key: &str) -> Result<(), BrightDataError> {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        let breaker = breakers.entry(key.to_string()).or_insert_with(Breaker::new);
+        let now = Instant::now();
+
+        match breaker.state {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open => {
+                let opened_at = breaker.opened_at.unwrap_or(now);
+                let elapsed = now.duration_since(opened_at);
+                if elapsed >= self.config.open_duration {
+                    breaker.state = BreakerState::HalfOpen;
+                    breaker.probe_started = Some(now);
+                    log::info!("Circuit {} half-open, probing upstream", key);
+                    Ok(())
+                } else {
+                    Err(BrightDataError::CircuitOpen {
+                        key: key.to_string(),
+                        retry_after: self.config.open_duration - elapsed,
+                    })
+                }
+            }
+            BreakerState::HalfOpen => {
+                // A probe that never reported back (e.g. a cancelled call)
+                // must not keep the breaker stuck half-open.
+                let stale = breaker
+                    .probe_started
+                    .map(|t| now.duration_since(t) >= self.config.open_duration)
+                    .unwrap_or(true);
+                if stale {
+                    breaker.probe_started = Some(now);
+                    Ok(())
+                } else {
+                    Err(BrightDataError::CircuitOpen {
+                        key: key.to_string(),
+                        retry_after: self.config.open_duration,
+                    })
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&self, key: &str) {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        let breaker = breakers.entry(key.to_string()).or_insert_with(Breaker::new);
+        if breaker.state != BreakerState::Closed {
+            log::info!("Circuit {} closed", key);
+        }
+        *breaker = Breaker::new();
+    }
+
+    pub fn record_failure(&self, key: &str) {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        let breaker = breakers.entry(key.to_string()).or_insert_with(Breaker::new);
+        breaker.consecutive_failures += 1;
+
+        let trip = breaker.state == BreakerState::HalfOpen
+            || breaker.consecutive_failures >= self.config.failure_threshold;
+        if trip && breaker.state != BreakerState::Open {
+            log::warn!(
+                "Circuit {} opened after {} consecutive failures",
+                key, breaker.consecutive_failures
+            );
+            breaker.state = BreakerState::Open;
+            breaker.opened_at = Some(Instant::now());
+            breaker.probe_started = None;
+        }
+    }
+
+    /// Run `call` under the breaker for `key`, recording its outcome.
+    pub async fn call<F>(&self, key: &str, call: F) -> Result<Retried<Response>, BrightDataError>
+    where
+        F: Future<Output = Result<Retried<Response>, BrightDataError>>,
+    {
+        self.acquire(key)?;
+        let res = call.await;
+        match &res {
+            Ok(r) => self.record_response(key, r.value.status(), r.value.headers()),
+            Err(e) => self.record_error(key, e),
+        }
+        res
+    }
+
+    /// Record a call that got no response. Only upstream failures count; an
+    /// expired call deadline or a request that could not be built is the
+    /// caller's doing.
+    pub fn record_error(&self, key: &str, err: &BrightDataError) {
+        let upstream = match err {
+            BrightDataError::Request(e) => !e.is_builder(),
+            BrightDataError::DeadlineExceeded(_) => false,
+            _ => true,
+        };
+        if upstream {
+            self.record_failure(key);
+        }
+    }
+
+    pub fn record_response(&self, key: &str, status: StatusCode, headers: &reqwest::header::HeaderMap) {
+        if is_failure(status, headers) {
+            self.record_failure(key);
+        } else {
+            self.record_success(key);
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<BreakerStatus> {
+        let breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statuses: Vec<BreakerStatus> = breakers
+            .iter()
+            .map(|(key, b)| BreakerStatus {
+                key: key.clone(),
+                state: b.state,
+                consecutive_failures: b.consecutive_failures,
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.key.cmp(&b.key));
+        statuses
+    }
+}
+
+/// Responses that say the zone or Bright Data itself is unhealthy, as
+/// opposed to the target site answering with an error.
+pub fn is_failure(status: StatusCode, headers: &reqwest::header::HeaderMap) -> bool {
+    status.is_server_error()
+        || status == StatusCode::PROXY_AUTHENTICATION_REQUIRED
+        || BRD_ERROR_HEADERS.iter().any(|h| headers.contains_key(*h))
+}
+
+pub fn key(zone: &str, endpoint: &str) -> String {
+    format!("{}@{}", zone, endpoint)
+}
+
+/// Breakers shared by the server, the tools and the library clients.
+pub fn global() -> Arc<CircuitBreakers> {
+    GLOBAL
+        .get_or_init(|| Arc::new(CircuitBreakers::new(BreakerConfig::from_env())))
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breakers() -> CircuitBreakers {
+        CircuitBreakers::new(BreakerConfig { failure_threshold: 2, open_duration: Duration::from_secs(30) })
+    }
+
+    #[test]
+    fn expired_deadlines_do_not_open_the_breaker() {
+        let breakers = breakers();
+        for _ in 0..5 {
+            breakers.record_error("zone@api", &crate::deadline::exceeded("scrape_website"));
+        }
+        assert!(breakers.acquire("zone@api").is_ok());
+    }
+
+    #[test]
+    fn upstream_failures_open_the_breaker() {
+        let breakers = breakers();
+        breakers.record_error("zone@api", &BrightDataError::Upstream(Default::default()));
+        assert!(breakers.acquire("zone@api").is_ok());
+        breakers.record_error("zone@api", &BrightDataError::Upstream(Default::default()));
+        assert!(matches!(breakers.acquire("zone@api"), Err(BrightDataError::CircuitOpen { .. })));
+    }
+}
@@ -0,0 +1,227 @@
+// src/browser.rs
+//! Scraping Browser access over the Chrome DevTools Protocol.
+//!
+//! Scraping Browser zones are remote Chrome instances reached with a
+//! websocket at `BROWSER_HOST` (default `brd.superproxy.io:9222`, or a full
+//! `ws://`/`wss://` URL), signed in with the proxy credentials of
+//! [`crate::proxy`] and the zone name. A fetch opens a tab, navigates it to
+//! the target, waits for the load event and returns the rendered HTML.
+
+use crate::error::{BrightDataError, ErrorContext};
+use crate::proxy::{ProxyConfig, ProxyTarget};
+use base64::Engine;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+pub const DEFAULT_BROWSER_HOST: &str = "brd.superproxy.io:9222";
+
+/// Longest a page may take to render when the call has no deadline.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Waits for the load event, then returns the rendered markup.
+const PAGE_HTML: &str = "new Promise(r => document.readyState === 'complete' ? r() : addEventListener('load', () => r()))\
+    .then(() => document.documentElement.outerHTML)";
+
+#[derive(Debug, Clone)]
+pub struct BrowserClient {
+    proxy: ProxyConfig,
+    host: String,
+}
+
+impl BrowserClient {
+    pub fn new(proxy: ProxyConfig, host: impl Into<String>) -> Self {
+        Self { proxy, host: host.into() }
+    }
+
+    pub fn from_env() -> Result<Self, BrightDataError> {
+        let host = env::var("BROWSER_HOST").unwrap_or_else(|_| DEFAULT_BROWSER_HOST.into());
+        Ok(Self::new(ProxyConfig::from_env()?, host))
+    }
+
+    /// Circuit breaker key for a Scraping Browser zone.
+    pub fn breaker_key(&self, zone: &str) -> String {
+        crate::breaker::key(zone, &self.host)
+    }
+
+    /// Render `url` in a tab of the Scraping Browser `zone` and return its
+    /// HTML, under the zone's concurrency limit and circuit breaker.
+    pub async fn fetch(&self, zone: &str, url: &str, deadline: Option<Instant>) -> Result<String, BrightDataError> {
+        let _permit = crate::limits::global().acquire(zone, url, deadline).await?;
+        let breakers = crate::breaker::global();
+        let key = self.breaker_key(zone);
+        breakers.acquire(&key)?;
+
+        let timeout = deadline.map(crate::deadline::remaining).unwrap_or(DEFAULT_TIMEOUT);
+        let res = match tokio::time::timeout(timeout, self.render(zone, url)).await {
+            Ok(res) => res,
+            Err(_) if deadline.is_some() => Err(crate::deadline::exceeded("browser")),
+            Err(_) => Err(BrightDataError::Timeout(context(zone, url, format!("page did not load within {}s", timeout.as_secs())))),
+        };
+        match &res {
+            Ok(_) => breakers.record_success(&key),
+            Err(err) => breakers.record_error(&key, err),
+        }
+        res
+    }
+
+    async fn render(&self, zone: &str, url: &str) -> Result<String, BrightDataError> {
+        let upstream = |message: String| BrightDataError::Upstream(context(zone, url, message));
+
+        let username = ProxyTarget::zone(zone).username(&self.proxy.username);
+        let credentials = base64::engine::general_purpose::STANDARD
+            .encode(format!("{}:{}", username, self.proxy.password.expose()));
+        let endpoint = match self.host.contains("://") {
+            true => self.host.clone(),
+            false => format!("wss://{}", self.host),
+        };
+        let mut request = endpoint
+            .into_client_request()
+            .map_err(|e| BrightDataError::InvalidParams(format!("Invalid BROWSER_HOST: {}", e)))?;
+        let authorization = format!("Basic {}", credentials)
+            .parse()
+            .map_err(|_| BrightDataError::InvalidParams("Invalid Scraping Browser credentials".into()))?;
+        request.headers_mut().insert("Authorization", authorization);
+
+        let (socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .map_err(|e| upstream(format!("Scraping Browser connection failed: {}", e)))?;
+        let mut cdp = Cdp { socket, next_id: 0 };
+
+        let target = cdp.call("Target.createTarget", json!({ "url": "about:blank" }), None).await.map_err(upstream)?;
+        let attached = cdp
+            .call("Target.attachToTarget", json!({ "targetId": target["targetId"], "flatten": true }), None)
+            .await
+            .map_err(upstream)?;
+        let session = attached["sessionId"].as_str();
+
+        let navigation = cdp.call("Page.navigate", json!({ "url": url }), session).await.map_err(upstream)?;
+        if let Some(err) = navigation["errorText"].as_str() {
+            return Err(upstream(format!("Navigation failed: {}", err)));
+        }
+        let page = cdp
+            .call("Runtime.evaluate", json!({ "expression": PAGE_HTML, "awaitPromise": true, "returnByValue": true }), session)
+            .await
+            .map_err(upstream)?;
+        let html = page["result"]["value"]
+            .as_str()
+            .ok_or_else(|| upstream("Page returned no HTML".into()))?
+            .to_string();
+
+        // Closing the connection ends the browser session and its tab.
+        let _ = cdp.socket.close(None).await;
+        Ok(html)
+    }
+}
+
+fn context(zone: &str, url: &str, message: String) -> ErrorContext {
+    ErrorContext {
+        zone: Some(zone.to_string()),
+        target_url: Some(url.to_string()),
+        retryable: true,
+        message,
+        ..Default::default()
+    }
+}
+
+/// A DevTools connection; commands are answered in order of their ids and
+/// events in between are skipped.
+struct Cdp {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+}
+
+impl Cdp {
+    async fn call(&mut self, method: &str, params: Value, session: Option<&str>) -> Result<Value, String> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let mut command = json!({ "id": id, "method": method, "params": params });
+        if let Some(session) = session {
+            command["sessionId"] = json!(session);
+        }
+        self.socket
+            .send(Message::Text(command.to_string()))
+            .await
+            .map_err(|e| format!("{} failed: {}", method, e))?;
+
+        while let Some(message) = self.socket.next().await {
+            let Message::Text(text) = message.map_err(|e| format!("{} failed: {}", method, e))? else {
+                continue;
+            };
+            let reply: Value = serde_json::from_str(&text).map_err(|e| format!("{}: invalid reply: {}", method, e))?;
+            if reply["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if let Some(err) = reply.get("error") {
+                return Err(format!("{} failed: {}", method, err["message"].as_str().unwrap_or("unknown error")));
+            }
+            return Ok(reply["result"].clone());
+        }
+        Err(format!("{}: Scraping Browser closed the connection", method))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// A DevTools endpoint that checks the credentials and serves `html`.
+    async fn browser(html: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            // The callback's error type is tungstenite's, not ours.
+            #[allow(clippy::result_large_err)]
+            let check = |req: &tokio_tungstenite::tungstenite::handshake::server::Request, res| {
+                let expected = format!("Basic {}", base64::engine::general_purpose::STANDARD.encode("brd-customer-1-zone-browser1:secret"));
+                assert_eq!(req.headers()["Authorization"], expected.as_str());
+                Ok(res)
+            };
+            let mut socket = tokio_tungstenite::accept_hdr_async(stream, check).await.unwrap();
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                let command: Value = serde_json::from_str(&text).unwrap();
+                let result = match command["method"].as_str().unwrap() {
+                    "Target.createTarget" => json!({ "targetId": "t1" }),
+                    "Target.attachToTarget" => json!({ "sessionId": "s1" }),
+                    "Page.navigate" => json!({ "frameId": "f1" }),
+                    "Runtime.evaluate" => {
+                        assert_eq!(command["sessionId"], "s1");
+                        json!({ "result": { "type": "string", "value": html } })
+                    }
+                    method => panic!("unexpected {}", method),
+                };
+                // Events in between replies are skipped.
+                socket.send(Message::Text(json!({ "method": "Page.loadEventFired" }).to_string())).await.unwrap();
+                socket.send(Message::Text(json!({ "id": command["id"], "result": result }).to_string())).await.unwrap();
+            }
+        });
+        format!("ws://{}", addr)
+    }
+
+    fn client(host: String) -> BrowserClient {
+        let proxy = ProxyConfig {
+            username: "brd-customer-1".into(),
+            password: crate::secret::Secret::new("secret".to_string()),
+            host: String::new(),
+            port: 0,
+            ca_cert: None,
+        };
+        BrowserClient::new(proxy, host)
+    }
+
+    #[tokio::test]
+    async fn renders_a_page_over_cdp() {
+        let html = client(browser("<html>rendered</html>").await)
+            .fetch("browser1", "https://example.com/", None)
+            .await
+            .unwrap();
+        assert_eq!(html, "<html>rendered</html>");
+    }
+}
@@ -0,0 +1,439 @@
+// src/budget.rs
+//! Estimated Bright Data spend and daily budgets.
+//!
+//! Prices are set per zone with `ZONE_PRICES`, as a request price, a
+//! bandwidth price or both:
+//! `ZONE_PRICES=web_unlocker=1.5/1k,residential=8.4/gb,isp=0.5/1k+4/gb,*=1.5/1k`
+//! (USD per request `/req`, per thousand requests `/1k`, per GB `/gb`).
+//!
+//! `BUDGET` sets daily limits in USD using the same keys as rate limits:
+//! `BUDGET=*=100,client=5,client:key:crawler=20,scrape_website=50,zone:residential=30`.
+//! Days are UTC. With `BUDGET_MODE=warn` an exhausted budget is only logged.
+//! Spend is kept in a [`StateStore`] so servers sharing a store share budgets.
+//! A request reserves its estimated cost up front, so concurrent requests
+//! cannot overshoot a budget, and settles the actual cost when it finishes.
+
+use crate::error::BrightDataError;
+use crate::ratelimit::LimitScope;
+use crate::store::{self, MemoryStore, StateStore};
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Weight of the newest response in a zone's average response size.
+const SIZE_SMOOTHING: f64 = 0.2;
+
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+/// How long a day's spend is kept in the store.
+const SPEND_TTL: Duration = Duration::from_secs(2 * 86400);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Price {
+    pub per_request: f64,
+    pub per_gb: f64,
+}
+
+impl Price {
+    /// Parse `1.5/1k`, `8.4/gb`, `0.002/req` or a `+` separated sum of them.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut price = Self::default();
+        for term in text.split('+').map(str::trim) {
+            let (amount, unit) = term
+                .split_once('/')
+                .ok_or_else(|| format!("Invalid price '{}', expected <usd>/<req|1k|gb>", term))?;
+            let amount: f64 = amount
+                .trim()
+                .parse()
+                .ok()
+                .filter(|a: &f64| a.is_finite() && *a >= 0.0)
+                .ok_or_else(|| format!("Invalid amount in price '{}'", term))?;
+            match unit.trim().to_ascii_lowercase().as_str() {
+                "req" => price.per_request += amount,
+                "1k" => price.per_request += amount / 1000.0,
+                "gb" => price.per_gb += amount,
+                _ => return Err(format!("Invalid unit in price '{}', expected req, 1k or gb", term)),
+            }
+        }
+        Ok(price)
+    }
+
+    pub fn cost(&self, requests: u32, bytes: u64) -> f64 {
+        self.per_request * requests as f64 + self.per_gb * bytes as f64 / BYTES_PER_GB
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CostModel {
+    zones: HashMap<String, Price>,
+    default: Price,
+}
+
+impl CostModel {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut model = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (zone, price) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid zone price '{}', expected <zone>=<price>", entry))?;
+            let price = Price::parse(price)?;
+            match zone.trim() {
+                "*" => model.default = price,
+                zone => {
+                    model.zones.insert(zone.to_string(), price);
+                }
+            }
+        }
+        Ok(model)
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(&env::var("ZONE_PRICES").unwrap_or_default())
+    }
+
+    pub fn price(&self, zone: &str) -> Price {
+        self.zones.get(zone).copied().unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BudgetMode {
+    #[default]
+    Reject,
+    Warn,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BudgetConfig {
+    pub prices: CostModel,
+    /// Daily limits in USD.
+    pub limits: HashMap<LimitScope, f64>,
+    pub mode: BudgetMode,
+}
+
+impl BudgetConfig {
+    pub fn parse(prices: &str, budgets: &str, mode: &str) -> Result<Self, String> {
+        let mut limits = HashMap::new();
+        for entry in budgets.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (key, amount) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid budget '{}', expected <key>=<usd>", entry))?;
+            let amount = amount
+                .trim()
+                .parse()
+                .ok()
+                .filter(|a: &f64| a.is_finite() && *a >= 0.0)
+                .ok_or_else(|| format!("Invalid amount in budget '{}'", entry))?;
+            limits.insert(LimitScope::parse(key), amount);
+        }
+        let mode = match mode.trim() {
+            "" | "reject" => BudgetMode::Reject,
+            "warn" => BudgetMode::Warn,
+            other => return Err(format!("Invalid BUDGET_MODE '{}', expected reject or warn", other)),
+        };
+        Ok(Self { prices: CostModel::parse(prices)?, limits, mode })
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let var = |key: &str| env::var(key).unwrap_or_default();
+        Self::parse(&var("ZONE_PRICES"), &var("BUDGET"), &var("BUDGET_MODE"))
+    }
+
+    /// Daily limit for `scope`; callers without an override get `client`.
+    fn limit(&self, scope: &LimitScope) -> Option<f64> {
+        let limit = self.limits.get(scope).copied();
+        match scope {
+            LimitScope::Client(_) => limit.or_else(|| self.limits.get(&LimitScope::Client("*".to_string())).copied()),
+            _ => limit,
+        }
+    }
+}
+
+/// Spend reserved for one request by [`Budgets::reserve`].
+#[derive(Debug)]
+#[must_use = "a reservation stays charged until it is settled"]
+pub struct Reservation {
+    /// Spend keys of the day the reservation was made.
+    keys: Vec<String>,
+    zone: String,
+    amount: f64,
+}
+
+impl Reservation {
+    /// Keep the reserved estimate as the final charge, for a request whose
+    /// actual cost is not known. Returns that estimate.
+    pub fn keep(self) -> f64 {
+        self.amount
+    }
+}
+
+/// Spend of one scope today.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpendStatus {
+    pub scope: String,
+    pub spent: f64,
+    pub budget: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct Budgets {
+    config: BudgetConfig,
+    store: Arc<dyn StateStore>,
+    avg_bytes: Mutex<HashMap<String, f64>>,
+    /// The current UTC day; replaced in tests.
+    today: fn() -> NaiveDate,
+}
+
+impl Default for Budgets {
+    fn default() -> Self {
+        Self::new(BudgetConfig::default())
+    }
+}
+
+fn scopes(client: &str, tool: &str, zone: &str) -> [LimitScope; 4] {
+    [
+        LimitScope::Global,
+        LimitScope::Client(client.to_string()),
+        LimitScope::Tool(tool.to_string()),
+        LimitScope::Zone(zone.to_string()),
+    ]
+}
+
+/// Store key prefix of a day's spend.
+fn day_prefix(day: NaiveDate) -> String {
+    format!("budget:{}:", day)
+}
+
+fn utc_today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+impl Budgets {
+    pub fn new(config: BudgetConfig) -> Self {
+        Self {
+            config,
+            store: Arc::new(MemoryStore::new()),
+            avg_bytes: Mutex::new(HashMap::new()),
+            today: utc_today,
+        }
+    }
+
+    /// Keep spend in `store` instead of this process.
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    pub fn config(&self) -> &BudgetConfig {
+        &self.config
+    }
+
+    fn avg_bytes(&self) -> std::sync::MutexGuard<'_, HashMap<String, f64>> {
+        self.avg_bytes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Expected cost of one request to `zone`, using its average response size.
+    pub fn estimate(&self, zone: &str) -> f64 {
+        let bytes = self.avg_bytes().get(zone).copied().unwrap_or_default();
+        self.config.prices.price(zone).cost(1, bytes as u64)
+    }
+
+    /// Reserve the expected cost of one request to `zone` against the
+    /// caller's, the tool's, the zone's and the overall budget, or fail if it
+    /// would take any of them past today's limit. Reserving is atomic, so
+    /// concurrent calls cannot overshoot a budget together. The reservation
+    /// stands until [`settle`](Self::settle) replaces it by the actual cost.
+    pub async fn reserve(&self, client: &str, tool: &str, zone: &str) -> Result<Reservation, BrightDataError> {
+        let estimate = self.estimate(zone);
+        let prefix = day_prefix((self.today)());
+        let scopes = scopes(client, tool, zone);
+        let keys: Vec<String> = scopes.iter().map(|scope| format!("{}{}", prefix, scope)).collect();
+        let limits: Vec<(String, Option<f64>)> =
+            scopes.iter().map(|scope| (scope.to_string(), self.config.limit(scope))).collect();
+        let reject = self.config.mode == BudgetMode::Reject;
+
+        let reserved = keys.clone();
+        let over = store::blocking(&self.store, move |store| {
+            let mut over = None;
+            store.update_many(&reserved, &mut |current| {
+                let spent: Vec<f64> = current.iter().map(|v| v.as_deref().and_then(|v| v.parse().ok()).unwrap_or_default()).collect();
+                over = limits.iter().zip(&spent).find_map(|((scope, limit), spent)| {
+                    let budget = (*limit)?;
+                    (spent + estimate > budget).then(|| BrightDataError::BudgetExceeded { scope: scope.clone(), spent: *spent, budget })
+                });
+                if over.is_some() && reject {
+                    return None;
+                }
+                Some(spent.iter().map(|spent| Some(((spent + estimate).to_string(), Some(SPEND_TTL)))).collect())
+            })?;
+            Ok(over)
+        })
+        .await?;
+
+        match over {
+            Some(err) if reject => Err(err),
+            Some(err) => {
+                log::warn!("{}", err);
+                Ok(Reservation { keys, zone: zone.to_string(), amount: estimate })
+            }
+            None => Ok(Reservation { keys, zone: zone.to_string(), amount: estimate }),
+        }
+    }
+
+    /// Replace a reservation by the cost of the request it was made for,
+    /// priced by its response size, and return that cost. A request that
+    /// failed (`None`) is not billed and costs nothing.
+    pub async fn settle(&self, reservation: Reservation, bytes: Option<u64>) -> f64 {
+        let cost = bytes.map_or(0.0, |bytes| self.config.prices.price(&reservation.zone).cost(1, bytes));
+        let delta = cost - reservation.amount;
+        if delta != 0.0 {
+            let keys = reservation.keys;
+            let settled = store::blocking(&self.store, move |store| {
+                store.update_many(&keys, &mut |current| {
+                    Some(
+                        current
+                            .iter()
+                            .map(|v| {
+                                let spent: f64 = v.as_deref().and_then(|v| v.parse().ok()).unwrap_or_default();
+                                Some(((spent + delta).max(0.0).to_string(), Some(SPEND_TTL)))
+                            })
+                            .collect(),
+                    )
+                })
+            })
+            .await;
+            if let Err(e) = settled {
+                log::warn!("Failed to record spend for zone {}: {}", reservation.zone, e);
+            }
+        }
+
+        if let Some(bytes) = bytes {
+            let mut avg_bytes = self.avg_bytes();
+            let avg = avg_bytes.entry(reservation.zone).or_insert(bytes as f64);
+            *avg += (bytes as f64 - *avg) * SIZE_SMOOTHING;
+        }
+        cost
+    }
+
+    /// Smallest budget left today among the scopes of a call, if any is limited.
+    pub async fn remaining(&self, client: &str, tool: &str, zone: &str) -> Option<f64> {
+        let prefix = day_prefix((self.today)());
+        let limited: Vec<(String, f64)> = scopes(client, tool, zone)
+            .iter()
+            .filter_map(|scope| Some((format!("{}{}", prefix, scope), self.config.limit(scope)?)))
+            .collect();
+        if limited.is_empty() {
+            return None;
+        }
+        let left = store::blocking(&self.store, move |store| {
+            limited
+                .iter()
+                .map(|(key, budget)| {
+                    let spent: f64 = store.get(key)?.and_then(|v| v.parse().ok()).unwrap_or_default();
+                    Ok((budget - spent).max(0.0))
+                })
+                .collect::<Result<Vec<f64>, BrightDataError>>()
+        })
+        .await;
+        left.inspect_err(|e| log::warn!("Failed to read spend: {}", e)).ok()?.into_iter().min_by(f64::total_cmp)
+    }
+
+    pub async fn snapshot(&self) -> Vec<SpendStatus> {
+        let prefix = day_prefix((self.today)());
+        let scan = prefix.clone();
+        let entries = store::blocking(&self.store, move |store| store.scan(&scan)).await.unwrap_or_else(|e| {
+            log::warn!("Failed to read spend: {}", e);
+            Vec::new()
+        });
+        let mut statuses: Vec<SpendStatus> = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let scope = entry.key.strip_prefix(&prefix)?.to_string();
+                Some(SpendStatus {
+                    budget: self.config.limit(&LimitScope::parse(&scope)),
+                    spent: entry.value.parse().ok()?,
+                    scope,
+                })
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.scope.cmp(&b.scope));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn configured(prices: &str, budget: &str, mode: &str) -> Budgets {
+        Budgets::new(BudgetConfig::parse(prices, budget, mode).unwrap())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_reservations_cannot_overshoot() {
+        let budgets = Arc::new(configured("*=1/req", "*=10", ""));
+        let calls = (0..50).map(|_| {
+            let budgets = budgets.clone();
+            tokio::spawn(async move { budgets.reserve("c", "scrape_website", "z").await.map(Reservation::keep) })
+        });
+        let reserved = futures::future::join_all(calls).await.into_iter().filter(|r| r.as_ref().unwrap().is_ok()).count();
+        assert_eq!(reserved, 10);
+        assert_eq!(budgets.remaining("c", "scrape_website", "z").await, Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn settling_replaces_the_estimate_by_the_actual_cost() {
+        let budgets = configured("*=1/req+1/gb", "client=10", "");
+        let reservation = budgets.reserve("c", "scrape_website", "z").await.unwrap();
+        assert_eq!(budgets.remaining("c", "scrape_website", "z").await, Some(9.0));
+
+        assert_eq!(budgets.settle(reservation, Some(1_000_000_000)).await, 2.0);
+        assert_eq!(budgets.remaining("c", "scrape_website", "z").await, Some(8.0));
+        // The next estimate includes the response size seen so far.
+        assert_eq!(budgets.estimate("z"), 2.0);
+
+        // A failed request gives back its whole reservation.
+        let reservation = budgets.reserve("c", "scrape_website", "z").await.unwrap();
+        assert_eq!(budgets.settle(reservation, None).await, 0.0);
+        assert_eq!(budgets.remaining("c", "scrape_website", "z").await, Some(8.0));
+    }
+
+    #[tokio::test]
+    async fn warn_mode_lets_calls_past_the_budget() {
+        let budgets = configured("*=1/req", "*=1", "warn");
+        for _ in 0..3 {
+            let _ = budgets.reserve("c", "scrape_website", "z").await.unwrap().keep();
+        }
+        let global = budgets.snapshot().await.into_iter().find(|s| s.scope == "*").unwrap();
+        assert_eq!((global.spent, global.budget), (3.0, Some(1.0)));
+
+        let strict = configured("*=1/req", "*=1", "reject");
+        let _ = strict.reserve("c", "scrape_website", "z").await.unwrap().keep();
+        assert!(matches!(
+            strict.reserve("c", "scrape_website", "z").await,
+            Err(BrightDataError::BudgetExceeded { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn budgets_start_over_on_the_next_utc_day() {
+        static DAY: AtomicU64 = AtomicU64::new(0);
+        fn today() -> NaiveDate {
+            NaiveDate::from_ymd_opt(2026, 1, 1).unwrap() + chrono::Days::new(DAY.load(Ordering::Relaxed))
+        }
+        let budgets = Budgets { today, ..configured("*=1/req", "*=1", "") };
+
+        let before_midnight = budgets.reserve("c", "scrape_website", "z").await.unwrap();
+        assert!(budgets.reserve("c", "scrape_website", "z").await.is_err());
+
+        DAY.store(1, Ordering::Relaxed);
+        let _ = budgets.reserve("c", "scrape_website", "z").await.unwrap().keep();
+        // Settling yesterday's request does not touch today's spend.
+        budgets.settle(before_midnight, None).await;
+        assert_eq!(budgets.remaining("c", "scrape_website", "z").await, Some(0.0));
+    }
+}
@@ -1,36 +1,98 @@
 // src/client.rs
+use crate::breaker::{self, CircuitBreakers};
+use crate::limits::{self, ConcurrencyLimiter};
+use crate::request::{self, SearchRequest};
+use crate::retry::{self, Retried, RetryPolicy};
 use crate::{config::BrightDataConfig, error::BrightDataError};
 use reqwest::Client;
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::sync::Arc;
 
+#[derive(Debug)]
 pub struct BrightDataClient {
     config: BrightDataConfig,
     client: Client,
+    retry: RetryPolicy,
+    breakers: Arc<CircuitBreakers>,
+    limiter: Arc<ConcurrencyLimiter>,
 }
 
 impl BrightDataClient {
     pub fn new(config: BrightDataConfig) -> Self {
+        Self::with_http_client(config, crate::http::shared())
+    }
+
+    pub fn with_http_client(config: BrightDataConfig, client: Client) -> Self {
         Self {
             config,
-            client: Client::new(),
+            client,
+            retry: RetryPolicy::from_env(),
+            breakers: breaker::global(),
+            limiter: limits::global(),
         }
     }
 
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Build a client for a named profile (see [`crate::profile`]).
+    pub fn from_profile(name: &str) -> Result<Self, BrightDataError> {
+        Ok(Self::new(crate::profile::load(Some(name))?))
+    }
+
+    pub fn config(&self) -> &BrightDataConfig {
+        &self.config
+    }
+
     pub async fn get(&self, target_url: &str) -> Result<Value, BrightDataError> {
         let payload = serde_json::json!({
             "url": target_url,
+            "zone": self.config.web_unlocker_zone,
         });
 
+        let res = self.post("get", &payload).await?;
+        Ok(serde_json::from_str(&res.value)?)
+    }
+
+    pub async fn search(&self, search: &SearchRequest) -> Result<Value, BrightDataError> {
+        let res = self.post("search", &request::search_payload(&self.config, search)).await?;
+        Ok(json!({ "raw": res.value, "attempts": res.attempts }))
+    }
+
+    pub async fn scrape(&self, url: &str) -> Result<Value, BrightDataError> {
+        let res = self.post("scrape", &request::scrape_payload(&self.config, url, true)).await?;
+        Ok(json!({ "content": res.value, "attempts": res.attempts }))
+    }
+
+    pub async fn extract(&self, url: &str) -> Result<Value, BrightDataError> {
+        let res = self.post("extract", &request::scrape_payload(&self.config, url, false)).await?;
+        Ok(json!({ "content": res.value, "attempts": res.attempts }))
+    }
+
+    /// POST `payload` to the request API with retries, under the zone's
+    /// concurrency limit and circuit breaker, and return the checked body.
+    pub async fn post(&self, label: &str, payload: &Value) -> Result<Retried<String>, BrightDataError> {
+        let zone = payload["zone"].as_str().unwrap_or_default();
+        let key = breaker::key(zone, &self.config.endpoint);
+        let _permit = self
+            .limiter
+            .acquire(zone, payload["url"].as_str().unwrap_or_default(), self.retry.deadline)
+            .await?;
         let res = self
-            .client
-            .post(&self.config.endpoint)
-            .header("Authorization", format!("Bearer {}", self.config.token))
-            .json(&payload)
-            .send()
-            .await?
-            .json::<Value>()
+            .breakers
+            .call(&key, retry::send(&self.retry, label, || {
+                self.client
+                    .post(&self.config.endpoint)
+                    .header("Authorization", request::authorization(&self.config))
+                    .json(payload)
+            }))
             .await?;
 
-        Ok(res)
+        let status = res.value.status();
+        let headers = res.value.headers().clone();
+        let text = request::check_response(status, &headers, res.value.text().await?, payload)?;
+        Ok(Retried { value: text, attempts: res.attempts })
     }
 }
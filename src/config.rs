@@ -1,14 +1,31 @@
 // src/config.rs
+use crate::secret::Secret;
 use anyhow::Result;
 
 #[derive(Clone, Debug)]
 pub struct BrightDataConfig {
     pub endpoint: String,
-    pub token: String,
+    pub token: Secret,
+    pub web_unlocker_zone: String,
+    pub browser_zone: String,
+    pub serp_zone: Option<String>,
 }
 
 impl BrightDataConfig {
     pub fn new(endpoint: String, token: String) -> Result<Self> {
-        Ok(Self { endpoint, token })
+        Ok(Self {
+            endpoint,
+            token: Secret::new(token),
+            web_unlocker_zone: "default_zone".to_string(),
+            browser_zone: "default_browser".to_string(),
+            serp_zone: None,
+        })
     }
-}
\ No newline at end of file
+
+    pub fn with_zones(mut self, web_unlocker_zone: String, browser_zone: String, serp_zone: Option<String>) -> Self {
+        self.web_unlocker_zone = web_unlocker_zone;
+        self.browser_zone = browser_zone;
+        self.serp_zone = serp_zone;
+        self
+    }
+}
@@ -0,0 +1,194 @@
+// src/cors.rs
+//! CORS for browser-based MCP clients.
+//!
+//! Cross-origin calls are refused unless the origin is listed in
+//! `CORS_ALLOWED_ORIGINS` (comma separated, e.g.
+//! `https://app.example.com,http://localhost:5173`, or `*` for any origin).
+//! `CORS_ALLOWED_METHODS`, `CORS_ALLOWED_HEADERS` and `CORS_EXPOSED_HEADERS`
+//! override the defaults below, `CORS_ALLOW_CREDENTIALS=true` lets browsers
+//! send cookies and auth headers and `CORS_MAX_AGE` sets how many seconds a
+//! preflight result is cached.
+
+use actix_cors::Cors;
+use actix_web::http::header::HeaderName;
+use actix_web::http::Method;
+use std::env;
+use std::str::FromStr;
+
+pub const DEFAULT_METHODS: &str = "GET, POST, OPTIONS";
+pub const DEFAULT_ALLOWED_HEADERS: &str = "Content-Type, Authorization, X-Api-Key, Mcp-Session-Id";
+/// Response headers MCP clients need to read: the session id, rate-limit
+/// state and the auth challenge.
+pub const DEFAULT_EXPOSED_HEADERS: &str =
+    "Mcp-Session-Id, RateLimit-Limit, RateLimit-Remaining, RateLimit-Reset, Retry-After, WWW-Authenticate";
+pub const DEFAULT_MAX_AGE: usize = 3600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    Any,
+    /// Exact origins (`scheme://host[:port]`); empty allows none.
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    pub origins: AllowedOrigins,
+    pub methods: Vec<Method>,
+    pub allowed_headers: Vec<HeaderName>,
+    pub exposed_headers: Vec<HeaderName>,
+    pub allow_credentials: bool,
+    /// Seconds browsers may cache a preflight response.
+    pub max_age: usize,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: AllowedOrigins::List(Vec::new()),
+            methods: parse_list(DEFAULT_METHODS).unwrap_or_default(),
+            allowed_headers: parse_list(DEFAULT_ALLOWED_HEADERS).unwrap_or_default(),
+            exposed_headers: parse_list(DEFAULT_EXPOSED_HEADERS).unwrap_or_default(),
+            allow_credentials: false,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+}
+
+fn parse_list<T: FromStr>(list: &str) -> Result<Vec<T>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| item.parse().map_err(|_| format!("Invalid CORS entry '{}'", item)))
+        .collect()
+}
+
+fn parse_origin(origin: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid CORS origin '{}', expected scheme://host[:port]", origin);
+    let url = reqwest::Url::parse(origin).map_err(|_| invalid())?;
+    let normalized = url.origin().ascii_serialization();
+    if normalized == "null" || normalized != origin.trim_end_matches('/') {
+        return Err(invalid());
+    }
+    Ok(normalized)
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    /// Read the `CORS_*` settings through `lookup`; empty values count as unset.
+    pub fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let var = |key: &str| lookup(key).filter(|v| !v.trim().is_empty());
+        let mut config = Self::default();
+
+        if let Some(origins) = var("CORS_ALLOWED_ORIGINS") {
+            config.origins = match origins.trim() {
+                "*" => AllowedOrigins::Any,
+                origins => AllowedOrigins::List(
+                    origins.split(',').map(str::trim).filter(|o| !o.is_empty()).map(parse_origin).collect::<Result<_, _>>()?,
+                ),
+            };
+        }
+        if let Some(methods) = var("CORS_ALLOWED_METHODS") {
+            config.methods = parse_list(&methods.to_ascii_uppercase())?;
+        }
+        if let Some(headers) = var("CORS_ALLOWED_HEADERS") {
+            config.allowed_headers = parse_list(&headers)?;
+        }
+        if let Some(headers) = var("CORS_EXPOSED_HEADERS") {
+            config.exposed_headers = parse_list(&headers)?;
+        }
+        if let Some(credentials) = var("CORS_ALLOW_CREDENTIALS") {
+            config.allow_credentials = credentials
+                .parse()
+                .map_err(|_| format!("Invalid CORS_ALLOW_CREDENTIALS '{}', expected true or false", credentials))?;
+        }
+        if let Some(max_age) = var("CORS_MAX_AGE") {
+            config.max_age = max_age.parse().map_err(|_| format!("Invalid CORS_MAX_AGE '{}'", max_age))?;
+        }
+
+        // Browsers reject credentialed responses that allow every origin.
+        if config.allow_credentials && config.origins == AllowedOrigins::Any {
+            return Err("CORS_ALLOW_CREDENTIALS needs explicit CORS_ALLOWED_ORIGINS, not *".to_string());
+        }
+        Ok(config)
+    }
+
+    /// Middleware applying this policy to every route, preflights included.
+    pub fn middleware(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.methods.clone())
+            .allowed_headers(self.allowed_headers.clone())
+            .expose_headers(self.exposed_headers.clone())
+            .max_age(self.max_age);
+        cors = match &self.origins {
+            AllowedOrigins::Any => cors.allow_any_origin().send_wildcard(),
+            AllowedOrigins::List(origins) => origins.iter().fold(cors, |cors, origin| cors.allowed_origin(origin)),
+        };
+        if self.allow_credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::collections::HashMap;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<CorsConfig, String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        CorsConfig::from_vars(|key| vars.get(key).cloned())
+    }
+
+    /// The `Access-Control-Allow-Origin` a request from `origin` gets.
+    async fn allowed_origin(config: &CorsConfig, origin: &str) -> Option<String> {
+        let app = init_service(
+            App::new()
+                .wrap(config.middleware())
+                .route("/mcp", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = TestRequest::post().uri("/mcp").insert_header(("Origin", origin)).to_request();
+        let res = call_service(&app, req).await;
+        res.headers()
+            .get("access-control-allow-origin")
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[actix_web::test]
+    async fn cross_origin_calls_are_denied_by_default() {
+        let config = from_vars(&[]).unwrap();
+        assert_eq!(config.origins, AllowedOrigins::List(Vec::new()));
+        assert_eq!(allowed_origin(&config, "https://evil.example").await, None);
+    }
+
+    #[actix_web::test]
+    async fn listed_origins_are_allowed() {
+        let config = from_vars(&[("CORS_ALLOWED_ORIGINS", "https://app.example.com, http://localhost:5173/")]).unwrap();
+        assert_eq!(
+            config.origins,
+            AllowedOrigins::List(vec!["https://app.example.com".into(), "http://localhost:5173".into()])
+        );
+        assert_eq!(
+            allowed_origin(&config, "https://app.example.com").await.as_deref(),
+            Some("https://app.example.com")
+        );
+        assert_eq!(allowed_origin(&config, "https://other.example.com").await, None);
+
+        assert!(from_vars(&[("CORS_ALLOWED_ORIGINS", "https://app.example.com/path")]).is_err());
+    }
+
+    #[test]
+    fn credentials_need_explicit_origins() {
+        let err = from_vars(&[("CORS_ALLOWED_ORIGINS", "*"), ("CORS_ALLOW_CREDENTIALS", "true")]).unwrap_err();
+        assert!(err.contains("CORS_ALLOW_CREDENTIALS"), "{}", err);
+        assert!(from_vars(&[("CORS_ALLOWED_ORIGINS", "https://app.example.com"), ("CORS_ALLOW_CREDENTIALS", "true")])
+            .unwrap()
+            .allow_credentials);
+    }
+}
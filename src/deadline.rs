@@ -0,0 +1,36 @@
+// src/deadline.rs
+//! Per-call deadlines.
+//!
+//! A `tools/call` may pass `timeout_ms` in its arguments. The value is capped
+//! by server policy and turned into one absolute deadline that every retry,
+//! fallback step and sub-request of the call shares, instead of each of them
+//! getting a fresh timeout.
+
+use crate::error::BrightDataError;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+pub const TIMEOUT_ARG: &str = "timeout_ms";
+
+/// The timeout requested in `args`, if any.
+pub fn requested(args: &Value) -> Option<Duration> {
+    args.get(TIMEOUT_ARG).and_then(|v| v.as_u64()).map(Duration::from_millis)
+}
+
+/// The deadline requested in `args`, falling back to `default` and never
+/// further away than `max`.
+pub fn from_args(args: &Value, default: Duration, max: Duration) -> Instant {
+    Instant::now() + requested(args).unwrap_or(default).min(max)
+}
+
+pub fn remaining(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
+pub fn is_expired(deadline: Instant) -> bool {
+    remaining(deadline).is_zero()
+}
+
+pub fn exceeded(label: &str) -> BrightDataError {
+    BrightDataError::DeadlineExceeded(label.to_string())
+}
@@ -0,0 +1,203 @@
+// src/detect.rs
+//! Block-page and CAPTCHA detection for responses that came back 200.
+//!
+//! Signatures are case-insensitive substrings, optionally scoped to a host:
+//! `BLOCK_SIGNATURES=google.com:unusual traffic|*:press & hold` adds to the
+//! built-in list. A page whose `<title>` matches one of the blocked titles,
+//! or whose body is shorter than `BLOCK_MIN_CONTENT_LENGTH`, is also treated
+//! as blocked. Proxy-mode scrapes retry a blocked page on a fresh session
+//! up to `BLOCK_MAX_ROTATIONS` times.
+
+use crate::error::{BrightDataError, ErrorContext};
+use std::env;
+use std::sync::{Arc, OnceLock};
+
+static GLOBAL: OnceLock<Arc<BlockDetector>> = OnceLock::new();
+
+/// Only the beginning of a page is inspected; block pages are small.
+const SCAN_LIMIT: usize = 32 * 1024;
+
+const DEFAULT_SIGNATURES: [(&str, &str); 9] = [
+    ("google.", "our systems have detected unusual traffic"),
+    ("google.", "/sorry/index"),
+    ("bing.com", "verify you are a human"),
+    ("yandex.", "smartcaptcha"),
+    ("duckduckgo.com", "anomaly-modal"),
+    ("*", "cf-challenge"),
+    ("*", "px-captcha"),
+    ("*", "captcha-delivery.com"),
+    ("*", "request unsuccessful. incapsula incident"),
+];
+
+const DEFAULT_BLOCKED_TITLES: [&str; 6] = [
+    "access denied",
+    "attention required",
+    "just a moment",
+    "robot check",
+    "are you a robot",
+    "security check",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// Host fragment the signature applies to, `None` for every site.
+    pub host: Option<String>,
+    pub pattern: String,
+}
+
+impl Signature {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (host, pattern) = spec.split_once(':')?;
+        let pattern = pattern.trim().to_ascii_lowercase();
+        if pattern.is_empty() {
+            return None;
+        }
+        let host = host.trim();
+        Some(Self {
+            host: (host != "*" && !host.is_empty()).then(|| host.to_ascii_lowercase()),
+            pattern,
+        })
+    }
+
+    fn applies_to(&self, host: &str) -> bool {
+        self.host.as_deref().map(|h| host.contains(h)).unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockDetector {
+    pub signatures: Vec<Signature>,
+    pub blocked_titles: Vec<String>,
+    pub min_content_length: usize,
+    pub max_rotations: u32,
+}
+
+impl Default for BlockDetector {
+    fn default() -> Self {
+        Self {
+            signatures: DEFAULT_SIGNATURES
+                .iter()
+                .filter_map(|(host, pattern)| Signature::parse(&format!("{host}:{pattern}")))
+                .collect(),
+            blocked_titles: DEFAULT_BLOCKED_TITLES.iter().map(|t| t.to_string()).collect(),
+            min_content_length: 0,
+            max_rotations: 2,
+        }
+    }
+}
+
+impl BlockDetector {
+    pub fn from_env() -> Self {
+        let mut detector = Self::default();
+
+        if let Ok(spec) = env::var("BLOCK_SIGNATURES") {
+            detector.signatures.extend(spec.split('|').filter_map(Signature::parse));
+        }
+        if let Ok(titles) = env::var("BLOCK_TITLES") {
+            detector
+                .blocked_titles
+                .extend(titles.split('|').map(|t| t.trim().to_ascii_lowercase()).filter(|t| !t.is_empty()));
+        }
+        if let Some(min) = env::var("BLOCK_MIN_CONTENT_LENGTH").ok().and_then(|v| v.parse().ok()) {
+            detector.min_content_length = min;
+        }
+        if let Some(max) = env::var("BLOCK_MAX_ROTATIONS").ok().and_then(|v| v.parse().ok()) {
+            detector.max_rotations = max;
+        }
+        detector
+    }
+
+    /// Why the page fetched from `url` looks like a block page, if it does.
+    pub fn classify(&self, url: &str, body: &str) -> Option<String> {
+        if body.trim().len() < self.min_content_length {
+            return Some(format!("body shorter than {} bytes", self.min_content_length));
+        }
+
+        let end = body.char_indices().nth(SCAN_LIMIT).map(|(i, _)| i).unwrap_or(body.len());
+        let head = body[..end].to_ascii_lowercase();
+        let host = crate::request::host_of(url).unwrap_or_default();
+
+        if let Some(sig) = self
+            .signatures
+            .iter()
+            .find(|s| s.applies_to(&host) && head.contains(&s.pattern))
+        {
+            return Some(format!("matched signature '{}'", sig.pattern));
+        }
+
+        let title = title_of(&head)?;
+        self.blocked_titles
+            .iter()
+            .find(|t| title.contains(t.as_str()))
+            .map(|t| format!("blocked page title '{}'", t))
+    }
+
+    /// `Err(Blocked)` when the page looks like a block page.
+    pub fn check(&self, url: &str, body: &str, zone: Option<&str>) -> Result<(), BrightDataError> {
+        match self.classify(url, body) {
+            Some(reason) => Err(BrightDataError::Blocked(ErrorContext {
+                status: Some(200),
+                zone: zone.map(str::to_string),
+                target_url: Some(url.to_string()),
+                retryable: true,
+                message: reason,
+                ..Default::default()
+            })),
+            None => Ok(()),
+        }
+    }
+}
+
+fn title_of(lower_html: &str) -> Option<String> {
+    let start = lower_html.find("<title")?;
+    let open_end = lower_html[start..].find('>')? + start + 1;
+    let close = lower_html[open_end..].find("</title>")? + open_end;
+    Some(lower_html[open_end..close].trim().to_string())
+}
+
+/// Detector shared by the server and the tools.
+pub fn global() -> Arc<BlockDetector> {
+    GLOBAL.get_or_init(|| Arc::new(BlockDetector::from_env())).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_apply_to_their_hosts() {
+        let detector = BlockDetector::default();
+        let sorry = "<html>Our systems have detected unusual traffic from your network</html>";
+        assert!(detector.classify("https://www.google.com/search?q=x", sorry).is_some());
+        // A page quoting Google's message elsewhere is not a block page.
+        assert!(detector.classify("https://example.com/", sorry).is_none());
+        // Site-wide signatures match on every host.
+        assert!(detector.classify("https://shop.example/", "<div id=\"px-captcha\"></div>").is_some());
+
+        assert_eq!(Signature::parse("Example.COM: Slow Down "), Some(Signature { host: Some("example.com".into()), pattern: "slow down".into() }));
+        assert_eq!(Signature::parse("*:x").unwrap().host, None);
+        assert_eq!(Signature::parse("example.com: "), None);
+        assert_eq!(Signature::parse("no separator"), None);
+    }
+
+    #[test]
+    fn blocked_titles_are_matched_case_insensitively() {
+        let detector = BlockDetector::default();
+        let page = "<html><head><TITLE lang=\"en\"> Just a moment... </TITLE></head></html>";
+        assert_eq!(detector.classify("https://example.com/", page), Some("blocked page title 'just a moment'".into()));
+        assert!(detector.classify("https://example.com/", "<title>Moments in history</title>").is_none());
+        assert!(detector.classify("https://example.com/", "no title at all").is_none());
+    }
+
+    #[test]
+    fn short_bodies_are_blocked_when_a_minimum_is_set() {
+        let detector = BlockDetector { min_content_length: 10, ..BlockDetector::default() };
+        assert!(detector.classify("https://example.com/", "  short  ").is_some());
+        assert!(detector.classify("https://example.com/", "long enough body").is_none());
+
+        let err = detector.check("https://example.com/", "", Some("unlocker")).unwrap_err();
+        assert_eq!(err.kind(), "blocked");
+        assert!(err.is_retryable());
+        assert_eq!(err.context().unwrap().zone.as_deref(), Some("unlocker"));
+    }
+}
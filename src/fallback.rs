@@ -0,0 +1,122 @@
+// src/fallback.rs
+//! Per-tool zone fallback chains.
+//!
+//! `ZONE_FALLBACK=scrape_website=unlocker>zone:unlocker_premium>proxy:residential,search_web=serp>unlocker`
+//! makes a blocked (or JavaScript-only) scrape retry on the `unlocker_premium`
+//! zone and then through the `residential` proxy zone. Steps:
+//!
+//! - `unlocker`, `serp`: the profile's zone of that kind, via the request API
+//! - `browser`: the profile's Scraping Browser zone, rendering the target URL
+//!   (see [`crate::browser`])
+//! - `zone:<name>`: any other zone via the request API
+//! - `proxy:<name>`: a proxy-mode zone, fetching the target URL directly
+
+use crate::config::BrightDataConfig;
+use crate::error::BrightDataError;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+
+/// Phrases of pages that only render with JavaScript enabled.
+const NEEDS_JS_MARKERS: [&str; 4] = [
+    "enable javascript",
+    "javascript is required",
+    "javascript is disabled",
+    "requires javascript",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZoneStep {
+    Unlocker,
+    Serp,
+    Browser,
+    Zone(String),
+    Proxy(String),
+}
+
+impl ZoneStep {
+    pub fn parse(step: &str) -> Result<Self, String> {
+        let step = step.trim();
+        match step {
+            "unlocker" => Ok(ZoneStep::Unlocker),
+            "serp" => Ok(ZoneStep::Serp),
+            "browser" => Ok(ZoneStep::Browser),
+            _ => match step.split_once(':') {
+                Some(("zone", name)) if !name.is_empty() => Ok(ZoneStep::Zone(name.to_string())),
+                Some(("proxy", name)) if !name.is_empty() => Ok(ZoneStep::Proxy(name.to_string())),
+                _ => Err(format!("Unknown fallback step '{}'", step)),
+            },
+        }
+    }
+
+    /// The zone name this step uses for `profile`.
+    pub fn zone<'a>(&'a self, profile: &'a BrightDataConfig) -> &'a str {
+        match self {
+            ZoneStep::Unlocker => &profile.web_unlocker_zone,
+            ZoneStep::Serp => profile.serp_zone.as_deref().unwrap_or(&profile.web_unlocker_zone),
+            ZoneStep::Browser => &profile.browser_zone,
+            ZoneStep::Zone(name) | ZoneStep::Proxy(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for ZoneStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZoneStep::Unlocker => f.write_str("unlocker"),
+            ZoneStep::Serp => f.write_str("serp"),
+            ZoneStep::Browser => f.write_str("browser"),
+            ZoneStep::Zone(name) => write!(f, "zone:{}", name),
+            ZoneStep::Proxy(name) => write!(f, "proxy:{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FallbackChains {
+    chains: HashMap<String, Vec<ZoneStep>>,
+}
+
+impl FallbackChains {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut chains = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (tool, steps) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid fallback entry '{}', expected tool=step>step", entry))?;
+            let steps = steps
+                .split('>')
+                .map(ZoneStep::parse)
+                .collect::<Result<Vec<_>, _>>()?;
+            chains.insert(tool.trim().to_string(), steps);
+        }
+        Ok(Self { chains })
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(&env::var("ZONE_FALLBACK").unwrap_or_default())
+    }
+
+    /// The chain for `tool`, or `default` when none is configured.
+    pub fn chain(&self, tool: &str, default: ZoneStep) -> Vec<ZoneStep> {
+        self.chains.get(tool).cloned().unwrap_or_else(|| vec![default])
+    }
+}
+
+/// Whether a failed step should move on to the next zone.
+pub fn should_fall_back(err: &BrightDataError) -> bool {
+    matches!(
+        err,
+        BrightDataError::Blocked(_)
+            | BrightDataError::ZoneNotFound(_)
+            | BrightDataError::Timeout(_)
+            | BrightDataError::Upstream(_)
+            | BrightDataError::CircuitOpen { .. }
+    )
+}
+
+/// Whether a successful body is a "please enable JavaScript" shell.
+pub fn needs_js(body: &str) -> bool {
+    let head: String = body.chars().take(4096).collect::<String>().to_ascii_lowercase();
+    NEEDS_JS_MARKERS.iter().any(|m| head.contains(m))
+}
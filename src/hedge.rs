@@ -0,0 +1,307 @@
+// src/hedge.rs
+//! Hedged requests for idempotent scrapes.
+//!
+//! When a fetch takes longer than the recent latency percentile, a second
+//! copy is started (on a fresh session in proxy mode) and whichever finishes
+//! first wins; the other one is dropped, which cancels it. Hedges are capped
+//! to a fraction of calls so the extra spend stays bounded, and the server
+//! counts each one against the zone's rate limit and the call's budgets.
+//!
+//! Off by default: set `HEDGE_ENABLED=true`, or pass `"hedge": true` on a
+//! call. Tuned with `HEDGE_PERCENTILE` (95), `HEDGE_INITIAL_DELAY_MS` (2000,
+//! used until enough latencies are known), `HEDGE_MIN_DELAY_MS` (250) and
+//! `HEDGE_MAX_RATIO` (0.1 extra requests per call).
+
+use crate::error::BrightDataError;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static GLOBAL: OnceLock<Arc<Hedger>> = OnceLock::new();
+
+/// Latencies kept for the percentile estimate.
+const WINDOW: usize = 256;
+/// Samples needed before the percentile replaces the initial delay.
+const MIN_SAMPLES: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct HedgeConfig {
+    pub enabled: bool,
+    pub percentile: f64,
+    pub initial_delay: Duration,
+    pub min_delay: Duration,
+    /// Maximum hedges per call, e.g. `0.1` for at most 10% extra requests.
+    pub max_ratio: f64,
+}
+
+impl Default for HedgeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            percentile: 95.0,
+            initial_delay: Duration::from_millis(2000),
+            min_delay: Duration::from_millis(250),
+            max_ratio: 0.1,
+        }
+    }
+}
+
+impl HedgeConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let ms = |key: &str, default: Duration| {
+            env::var(key).ok().and_then(|v| v.parse().ok()).map(Duration::from_millis).unwrap_or(default)
+        };
+        Self {
+            enabled: env::var("HEDGE_ENABLED").map(|v| v == "true" || v == "1").unwrap_or(defaults.enabled),
+            percentile: env::var("HEDGE_PERCENTILE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|p: f64| p.clamp(1.0, 100.0))
+                .unwrap_or(defaults.percentile),
+            initial_delay: ms("HEDGE_INITIAL_DELAY_MS", defaults.initial_delay),
+            min_delay: ms("HEDGE_MIN_DELAY_MS", defaults.min_delay),
+            max_ratio: env::var("HEDGE_MAX_RATIO")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|r: f64| r.max(0.0))
+                .unwrap_or(defaults.max_ratio),
+        }
+    }
+
+    /// Whether a call hedges: the `hedge` argument wins over `HEDGE_ENABLED`.
+    pub fn enabled_for(&self, args: &Value) -> bool {
+        args.get("hedge").and_then(|v| v.as_bool()).unwrap_or(self.enabled)
+    }
+}
+
+/// Result of a hedged call.
+#[derive(Debug)]
+pub struct Hedged<T> {
+    pub value: T,
+    /// A second request was started.
+    pub hedged: bool,
+    /// The second request produced the result.
+    pub hedge_won: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HedgeStats {
+    pub calls: u64,
+    pub hedges: u64,
+    pub hedge_wins: u64,
+    pub delay_ms: u64,
+}
+
+#[derive(Debug)]
+pub struct Hedger {
+    config: HedgeConfig,
+    latencies: Mutex<VecDeque<Duration>>,
+    calls: AtomicU64,
+    hedges: AtomicU64,
+    hedge_wins: AtomicU64,
+}
+
+impl Hedger {
+    pub fn new(config: HedgeConfig) -> Self {
+        Self {
+            config,
+            latencies: Mutex::new(VecDeque::with_capacity(WINDOW)),
+            calls: AtomicU64::new(0),
+            hedges: AtomicU64::new(0),
+            hedge_wins: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(HedgeConfig::from_env())
+    }
+
+    pub fn config(&self) -> &HedgeConfig {
+        &self.config
+    }
+
+    /// How long the first request runs before a hedge is started.
+    pub fn delay(&self) -> Duration {
+        let latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+        if latencies.len() < MIN_SAMPLES {
+            return self.config.initial_delay;
+        }
+        let mut sorted: Vec<Duration> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = ((self.config.percentile / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1].max(self.config.min_delay)
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+        if latencies.len() == WINDOW {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// Reserve one hedge if that keeps hedges within `max_ratio` of calls.
+    fn take_budget(&self) -> bool {
+        let calls = self.calls.load(Ordering::Relaxed) as f64;
+        let allowed = (calls * self.config.max_ratio).floor() as u64;
+        self.hedges
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |hedges| (hedges < allowed).then_some(hedges + 1))
+            .is_ok()
+    }
+
+    /// Run `make(false)`; if it is still pending after [`delay`](Self::delay),
+    /// the budget allows and `admit` accepts a second request, race it against
+    /// `make(true)`. The loser is dropped. If the first one to finish fails,
+    /// the other one is awaited. A hedge `admit` refuses is not sent and does
+    /// not count against `max_ratio`.
+    pub async fn run<T, F, Fut, A, AFut>(&self, enabled: bool, admit: A, mut make: F) -> Result<Hedged<T>, BrightDataError>
+    where
+        F: FnMut(bool) -> Fut,
+        Fut: Future<Output = Result<T, BrightDataError>>,
+        A: FnOnce() -> AFut,
+        AFut: Future<Output = Result<(), BrightDataError>>,
+    {
+        let started = Instant::now();
+        let primary = make(false);
+        tokio::pin!(primary);
+
+        if !enabled {
+            return self.finish(primary.await, started);
+        }
+
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let delay = self.delay();
+        tokio::select! {
+            res = &mut primary => return self.finish(res, started),
+            _ = tokio::time::sleep(delay) => {}
+        }
+        if !self.take_budget() {
+            return self.finish(primary.await, started);
+        }
+        // Not raced against the first request: an admitted hedge has been
+        // charged, so it is sent.
+        if let Err(err) = admit().await {
+            log::debug!("Hedge not sent: {}", err);
+            self.hedges.fetch_sub(1, Ordering::Relaxed);
+            return self.finish(primary.await, started);
+        }
+
+        log::debug!("Request slower than {:?}, sending a hedge", delay);
+        let hedge = make(true);
+        tokio::pin!(hedge);
+
+        let (res, from_hedge) = tokio::select! {
+            res = &mut primary => (res, false),
+            res = &mut hedge => (res, true),
+        };
+        let (res, from_hedge) = match res {
+            Ok(value) => (Ok(value), from_hedge),
+            Err(err) => {
+                log::debug!("First finished request failed ({}), waiting for the other", err);
+                let other = if from_hedge { (&mut primary).await } else { (&mut hedge).await };
+                (other, !from_hedge)
+            }
+        };
+
+        let hedge_won = from_hedge && res.is_ok();
+        if hedge_won {
+            self.hedge_wins.fetch_add(1, Ordering::Relaxed);
+        } else if res.is_ok() {
+            self.record_latency(started.elapsed());
+        }
+        res.map(|value| Hedged { value, hedged: true, hedge_won })
+    }
+
+    fn finish<T>(&self, res: Result<T, BrightDataError>, started: Instant) -> Result<Hedged<T>, BrightDataError> {
+        let value = res?;
+        self.record_latency(started.elapsed());
+        Ok(Hedged { value, hedged: false, hedge_won: false })
+    }
+
+    pub fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            hedges: self.hedges.load(Ordering::Relaxed),
+            hedge_wins: self.hedge_wins.load(Ordering::Relaxed),
+            delay_ms: self.delay().as_millis() as u64,
+        }
+    }
+}
+
+/// Hedger shared by the server and the tools.
+pub fn global() -> Arc<Hedger> {
+    GLOBAL.get_or_init(|| Arc::new(Hedger::from_env())).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hedger() -> Hedger {
+        Hedger::new(HedgeConfig {
+            enabled: true,
+            initial_delay: Duration::from_millis(10),
+            max_ratio: 1.0,
+            ..HedgeConfig::default()
+        })
+    }
+
+    async fn respond(after_ms: u64, ok: bool) -> Result<&'static str, BrightDataError> {
+        tokio::time::sleep(Duration::from_millis(after_ms)).await;
+        if ok { Ok("body") } else { Err(BrightDataError::ToolError("failed".into())) }
+    }
+
+    #[tokio::test]
+    async fn a_faster_hedge_wins() {
+        let hedger = hedger();
+        let res = hedger
+            .run(true, || async { Ok(()) }, |is_hedge| respond(if is_hedge { 0 } else { 200 }, true))
+            .await
+            .unwrap();
+        assert!(res.hedged && res.hedge_won);
+        assert_eq!((hedger.stats().hedges, hedger.stats().hedge_wins), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn a_failed_hedge_is_not_a_win() {
+        let hedger = hedger();
+        // The first request fails first, then the hedge fails too.
+        let res = hedger.run(true, || async { Ok(()) }, |is_hedge| respond(if is_hedge { 50 } else { 20 }, false)).await;
+        assert!(res.is_err());
+        assert_eq!(hedger.stats().hedge_wins, 0);
+
+        // The hedge fails first and the first request then succeeds.
+        let res = hedger
+            .run(true, || async { Ok(()) }, |is_hedge| respond(if is_hedge { 0 } else { 50 }, !is_hedge))
+            .await
+            .unwrap();
+        assert!(res.hedged && !res.hedge_won);
+        assert_eq!(hedger.stats().hedge_wins, 0);
+    }
+
+    #[tokio::test]
+    async fn a_refused_hedge_is_not_sent_or_counted() {
+        let hedger = hedger();
+        let mut sent = 0;
+        let res = hedger
+            .run(
+                true,
+                || async { Err(BrightDataError::ToolError("over budget".into())) },
+                |is_hedge| {
+                    sent += is_hedge as u32;
+                    respond(50, true)
+                },
+            )
+            .await
+            .unwrap();
+        assert!(!res.hedged);
+        assert_eq!(sent, 0);
+        assert_eq!(hedger.stats().hedges, 0);
+    }
+}
@@ -0,0 +1,203 @@
+// src/http.rs
+//! The process-wide HTTP client.
+//!
+//! Building a `reqwest::Client` per request throws away its connection pool
+//! and TLS sessions, so every tool, the library clients and the server share
+//! one client configured from [`HttpClientConfig`].
+
+use crate::error::BrightDataError;
+use reqwest::{Certificate, Client, ClientBuilder, Proxy};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+static CONFIG: OnceLock<HttpClientConfig> = OnceLock::new();
+static SHARED: OnceLock<Client> = OnceLock::new();
+static PROXIED: OnceLock<Mutex<ProxiedClients>> = OnceLock::new();
+#[cfg(feature = "blocking")]
+static SHARED_BLOCKING: OnceLock<reqwest::blocking::Client> = OnceLock::new();
+
+/// Proxied clients are cached per proxy URL; past this many the least
+/// recently used one is dropped.
+const MAX_PROXIED_CLIENTS: usize = 64;
+
+#[derive(Default)]
+struct ProxiedClients {
+    /// Client and the tick it was last handed out at, by proxy URL.
+    clients: HashMap<String, (Client, u64)>,
+    tick: u64,
+    /// Parsed CA certificates, by path.
+    certs: HashMap<PathBuf, Certificate>,
+}
+
+impl ProxiedClients {
+    fn cert(&mut self, path: &Path) -> Result<Certificate, BrightDataError> {
+        if let Some(cert) = self.certs.get(path) {
+            return Ok(cert.clone());
+        }
+        let cert = Certificate::from_pem(&std::fs::read(path)?)?;
+        self.certs.insert(path.to_path_buf(), cert.clone());
+        Ok(cert)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpClientConfig {
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Duration,
+    pub tcp_keepalive: Option<Duration>,
+    pub http2_prior_knowledge: bool,
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub gzip: bool,
+    pub brotli: bool,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: Duration::from_secs(90),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            http2_prior_knowledge: false,
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            gzip: true,
+            brotli: true,
+        }
+    }
+}
+
+impl HttpClientConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |key: &str| env::var(key).ok().and_then(|v| v.parse::<u64>().ok()).map(Duration::from_secs);
+        let flag = |key: &str, default: bool| {
+            env::var(key)
+                .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+                .unwrap_or(default)
+        };
+
+        Self {
+            pool_max_idle_per_host: env::var("HTTP_POOL_MAX_IDLE_PER_HOST")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.pool_max_idle_per_host),
+            pool_idle_timeout: secs("HTTP_POOL_IDLE_TIMEOUT").unwrap_or(defaults.pool_idle_timeout),
+            // 0 disables TCP keepalive.
+            tcp_keepalive: match secs("HTTP_TCP_KEEPALIVE") {
+                Some(d) if d.is_zero() => None,
+                Some(d) => Some(d),
+                None => defaults.tcp_keepalive,
+            },
+            http2_prior_knowledge: flag("HTTP2_PRIOR_KNOWLEDGE", defaults.http2_prior_knowledge),
+            connect_timeout: secs("HTTP_CONNECT_TIMEOUT").unwrap_or(defaults.connect_timeout),
+            timeout: secs("REQUEST_TIMEOUT").unwrap_or(defaults.timeout),
+            gzip: flag("HTTP_GZIP", defaults.gzip),
+            brotli: flag("HTTP_BROTLI", defaults.brotli),
+        }
+    }
+
+    /// A builder with every setting applied, for callers that need to add
+    /// something (e.g. a proxy) before building.
+    pub fn builder(&self) -> ClientBuilder {
+        let mut builder = Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive)
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .gzip(self.gzip)
+            .brotli(self.brotli);
+
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        builder
+    }
+
+    pub fn build(&self) -> reqwest::Result<Client> {
+        self.builder().build()
+    }
+
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(&self) -> reqwest::Result<reqwest::blocking::Client> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive)
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .gzip(self.gzip)
+            .brotli(self.brotli);
+
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        builder.build()
+    }
+}
+
+/// Configure every client this module builds, the shared one and the
+/// proxied ones, and return the shared client. Must run before any client is
+/// built: once one is, the configuration is fixed and a different `config`
+/// is ignored with a warning.
+pub fn init(config: &HttpClientConfig) -> Client {
+    if CONFIG.set(config.clone()).is_err() && CONFIG.get() != Some(config) {
+        log::warn!("HTTP clients were already built; ignoring the configuration passed to http::init");
+    }
+    shared()
+}
+
+/// The configuration clients are built from: the one given to [`init`], or
+/// [`HttpClientConfig::from_env`] if a client was needed first.
+pub fn config() -> &'static HttpClientConfig {
+    CONFIG.get_or_init(HttpClientConfig::from_env)
+}
+
+/// The shared client, built from [`config`] on first use.
+pub fn shared() -> Client {
+    SHARED
+        .get_or_init(|| config().build().expect("Failed to build HTTP client"))
+        .clone()
+}
+
+/// A pooled client that sends everything through `proxy_url`, trusting the
+/// extra root certificate at `ca_cert` if given. A proxy is a property of the
+/// whole `reqwest::Client`, so there is one cached client per proxy URL; the
+/// certificate is read once per path.
+pub fn proxied(proxy_url: &str, ca_cert: Option<&Path>) -> Result<Client, BrightDataError> {
+    let cache = PROXIED.get_or_init(Default::default);
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    cache.tick += 1;
+    let tick = cache.tick;
+
+    if let Some((client, used)) = cache.clients.get_mut(proxy_url) {
+        *used = tick;
+        return Ok(client.clone());
+    }
+
+    let mut builder = config().builder().proxy(Proxy::all(proxy_url)?);
+    if let Some(path) = ca_cert {
+        builder = builder.add_root_certificate(cache.cert(path)?);
+    }
+    let client = builder.build()?;
+
+    if cache.clients.len() >= MAX_PROXIED_CLIENTS {
+        let oldest = cache.clients.iter().min_by_key(|(_, (_, used))| *used).map(|(url, _)| url.clone());
+        if let Some(url) = oldest {
+            cache.clients.remove(&url);
+        }
+    }
+    cache.clients.insert(proxy_url.to_string(), (client.clone(), tick));
+    Ok(client)
+}
+
+#[cfg(feature = "blocking")]
+pub fn shared_blocking() -> reqwest::blocking::Client {
+    SHARED_BLOCKING
+        .get_or_init(|| config().build_blocking().expect("Failed to build HTTP client"))
+        .clone()
+}
@@ -0,0 +1,169 @@
+// src/identity.rs
+//! Who is calling the MCP server.
+//!
+//! Callers authenticated with a configured API key are `key:<name>` (see
+//! `auth`). Everyone else is told apart by client IP (`ip:<addr>`) only:
+//! unchecked keys and client-chosen session ids would let a caller pick a
+//! fresh identity, and so fresh limits, on every request.
+//! `X-Forwarded-For` is only believed when the connection comes from one of
+//! `TRUSTED_PROXIES` (IPs or CIDR ranges).
+
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IP address or CIDR range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| format!("Invalid IP address '{}'", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse().ok().filter(|p| *p <= max).ok_or_else(|| format!("Invalid prefix in '{}'", s))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(IpNet::from_str)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// `TRUSTED_PROXIES`, empty when unset.
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(&env::var("TRUSTED_PROXIES").unwrap_or_default())
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+
+    /// The client address for a connection from `peer` carrying
+    /// `X-Forwarded-For: forwarded`. Hops are read right to left and trusted
+    /// proxies are skipped; the first other address is the client.
+    pub fn client_ip(&self, peer: IpAddr, forwarded: Option<&str>) -> IpAddr {
+        if !self.contains(&peer) {
+            return peer;
+        }
+        let hops: Vec<IpAddr> = forwarded
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        hops.iter()
+            .rev()
+            .find(|ip| !self.contains(ip))
+            .or(hops.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+/// Identity of a caller without a configured API key.
+pub fn client_id(ip: Option<IpAddr>) -> String {
+    match ip {
+        Some(ip) => format!("ip:{}", ip),
+        None => "anonymous".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ip_net_contains() {
+        let net: IpNet = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(&ip("10.1.255.7")));
+        assert!(!net.contains(&ip("10.2.0.1")));
+        assert!(!net.contains(&ip("::ffff:10.1.0.1")));
+
+        let host: IpNet = "192.168.1.5".parse().unwrap();
+        assert!(host.contains(&ip("192.168.1.5")));
+        assert!(!host.contains(&ip("192.168.1.6")));
+
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&ip("203.0.113.9")));
+
+        let v6: IpNet = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(&ip("fd12:3456::1")));
+        assert!(!v6.contains(&ip("fe80::1")));
+
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("not-an-ip".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        assert_eq!(proxies.client_ip(ip("203.0.113.9"), Some("1.2.3.4")), ip("203.0.113.9"));
+        assert_eq!(TrustedProxies::default().client_ip(ip("10.0.0.1"), Some("1.2.3.4")), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_hops_are_read_right_to_left() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 172.16.0.1").unwrap();
+        // The left-most hop is whatever the client claimed.
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.2"), Some("6.6.6.6, 198.51.100.7, 172.16.0.1")),
+            ip("198.51.100.7")
+        );
+        assert_eq!(proxies.client_ip(ip("10.0.0.2"), Some("10.0.0.3, 172.16.0.1")), ip("10.0.0.3"));
+        assert_eq!(proxies.client_ip(ip("10.0.0.2"), None), ip("10.0.0.2"));
+        assert_eq!(proxies.client_ip(ip("10.0.0.2"), Some("garbage")), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn client_ids() {
+        assert_eq!(client_id(Some(ip("203.0.113.9"))), "ip:203.0.113.9");
+        assert_eq!(client_id(None), "anonymous");
+    }
+}
@@ -1,15 +1,42 @@
 // src/lib.rs
 pub mod config;
+pub mod cors;
+pub mod profile;
+pub mod secret;
+pub mod deadline;
+pub mod detect;
 pub mod error;
 pub mod types;
+pub mod usage;
+pub mod auth;
+pub mod breaker;
+pub mod browser;
+pub mod budget;
 pub mod client;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod fallback;
+pub mod hedge;
+pub mod http;
+pub mod identity;
+pub mod limits;
+pub mod proxy;
+pub mod ratelimit;
+pub mod request;
+pub mod retry;
 pub mod rpc_client;
 pub mod tool;
 pub mod tools;
 pub mod server;
+pub mod session;
+pub mod store;
 
 // Optional re-exports from the correct module
 pub use server::{
     AppState, BrightDataUrls, Config,
-    cors_handler, handle_mcp_request, health_check,
+    handle_mcp_request, health_check, metrics, usage_report,
 };
+
+pub use client::BrightDataClient;
+pub use config::BrightDataConfig;
+pub use request::SearchRequest;
@@ -0,0 +1,288 @@
+// src/limits.rs
+//! Concurrency limits on outbound Bright Data traffic.
+//!
+//! Three independent caps apply to every request: a global one
+//! (`MAX_CONCURRENCY`), one per zone (`ZONE_CONCURRENCY=unlocker=10,*=5`) and
+//! one per target host (`HOST_CONCURRENCY`). Callers over a cap wait in a FIFO
+//! queue for at most `QUEUE_TIMEOUT_MS` (or the call deadline, if sooner).
+
+use crate::error::BrightDataError;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+static GLOBAL: OnceLock<Arc<ConcurrencyLimiter>> = OnceLock::new();
+#[cfg(feature = "blocking")]
+static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+/// Idle per-host semaphores are dropped once the map grows past this size.
+const MAX_TRACKED_HOSTS: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct ConcurrencyConfig {
+    pub global: Option<usize>,
+    pub per_zone: HashMap<String, usize>,
+    pub default_per_zone: Option<usize>,
+    pub per_host: Option<usize>,
+    pub queue_timeout: Duration,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            global: None,
+            per_zone: HashMap::new(),
+            default_per_zone: None,
+            per_host: None,
+            queue_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let number = |key: &str| env::var(key).ok().and_then(|v| v.parse::<usize>().ok()).filter(|n| *n > 0);
+
+        config.global = number("MAX_CONCURRENCY");
+        config.per_host = number("HOST_CONCURRENCY");
+        if let Some(ms) = env::var("QUEUE_TIMEOUT_MS").ok().and_then(|v| v.parse().ok()) {
+            config.queue_timeout = Duration::from_millis(ms);
+        }
+
+        for entry in env::var("ZONE_CONCURRENCY").unwrap_or_default().split(',') {
+            let Some((zone, limit)) = entry.split_once('=') else { continue };
+            let Ok(limit) = limit.trim().parse::<usize>() else { continue };
+            match zone.trim() {
+                "*" => config.default_per_zone = Some(limit),
+                zone => {
+                    config.per_zone.insert(zone.to_string(), limit);
+                }
+            }
+        }
+        config
+    }
+
+    fn zone_limit(&self, zone: &str) -> Option<usize> {
+        self.per_zone.get(zone).copied().or(self.default_per_zone)
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+}
+
+impl Slot {
+    fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            waiting: AtomicUsize::new(0),
+        })
+    }
+
+    fn in_flight(&self) -> usize {
+        self.limit - self.semaphore.available_permits()
+    }
+}
+
+/// Holding this keeps the caller's slots; they are released on drop.
+#[derive(Debug)]
+pub struct Permit {
+    _permits: Vec<OwnedSemaphorePermit>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub scope: String,
+    pub limit: usize,
+    pub in_flight: usize,
+    pub waiting: usize,
+}
+
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    config: ConcurrencyConfig,
+    global: Option<Arc<Slot>>,
+    zones: Mutex<HashMap<String, Arc<Slot>>>,
+    hosts: Mutex<HashMap<String, Arc<Slot>>>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(config: ConcurrencyConfig) -> Self {
+        Self {
+            global: config.global.map(Slot::new),
+            config,
+            zones: Mutex::new(HashMap::new()),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a global, zone and host slot for a request to `url` through
+    /// `zone`. Slots are always taken in that order so callers cannot
+    /// deadlock each other.
+    pub async fn acquire(&self, zone: &str, url: &str, deadline: Option<Instant>) -> Result<Permit, BrightDataError> {
+        let mut wait = self.config.queue_timeout;
+        if let Some(deadline) = deadline {
+            wait = wait.min(crate::deadline::remaining(deadline));
+        }
+        let until = Instant::now() + wait;
+
+        let mut slots = Vec::with_capacity(3);
+        if let Some(global) = &self.global {
+            slots.push(("global".to_string(), global.clone()));
+        }
+        if let Some(limit) = self.config.zone_limit(zone) {
+            let slot = slot_for(&self.zones, zone, limit, usize::MAX);
+            slots.push((format!("zone:{}", zone), slot));
+        }
+        if let Some(limit) = self.config.per_host {
+            let host = crate::request::host_of(url).unwrap_or_default();
+            let slot = slot_for(&self.hosts, &host, limit, MAX_TRACKED_HOSTS);
+            slots.push((format!("host:{}", host), slot));
+        }
+
+        let mut permits = Vec::with_capacity(slots.len());
+        for (scope, slot) in slots {
+            slot.waiting.fetch_add(1, Ordering::SeqCst);
+            let remaining = until.saturating_duration_since(Instant::now());
+            let acquired = tokio::time::timeout(remaining, slot.semaphore.clone().acquire_owned()).await;
+            slot.waiting.fetch_sub(1, Ordering::SeqCst);
+
+            match acquired {
+                Ok(Ok(permit)) => permits.push(permit),
+                _ => {
+                    log::warn!("Concurrency queue for {} timed out after {:?}", scope, wait);
+                    return Err(BrightDataError::QueueTimeout { scope, waited: wait });
+                }
+            }
+        }
+        Ok(Permit { _permits: permits })
+    }
+
+    /// Blocking counterpart of [`acquire`](Self::acquire), for the blocking
+    /// client. It must not be called from inside an async runtime.
+    #[cfg(feature = "blocking")]
+    pub fn acquire_blocking(&self, zone: &str, url: &str, deadline: Option<Instant>) -> Result<Permit, BrightDataError> {
+        let runtime = RUNTIME.get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("snm-limits")
+                .enable_time()
+                .build()
+                .expect("Failed to build the concurrency limiter runtime")
+        });
+        runtime.block_on(self.acquire(zone, url, deadline))
+    }
+
+    pub fn stats(&self) -> Vec<QueueStats> {
+        let describe = |scope: String, slot: &Slot| QueueStats {
+            scope,
+            limit: slot.limit,
+            in_flight: slot.in_flight(),
+            waiting: slot.waiting.load(Ordering::SeqCst),
+        };
+
+        let mut stats = Vec::new();
+        if let Some(global) = &self.global {
+            stats.push(describe("global".to_string(), global));
+        }
+        for (zone, slot) in self.zones.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            stats.push(describe(format!("zone:{}", zone), slot));
+        }
+        for (host, slot) in self.hosts.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            stats.push(describe(format!("host:{}", host), slot));
+        }
+        stats.sort_by(|a, b| a.scope.cmp(&b.scope));
+        stats
+    }
+}
+
+fn slot_for(map: &Mutex<HashMap<String, Arc<Slot>>>, key: &str, limit: usize, max_entries: usize) -> Arc<Slot> {
+    let mut map = map.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(slot) = map.get(key) {
+        return slot.clone();
+    }
+    if map.len() >= max_entries {
+        map.retain(|_, slot| Arc::strong_count(slot) > 1 || slot.in_flight() > 0);
+    }
+    let slot = Slot::new(limit);
+    map.insert(key.to_string(), slot.clone());
+    slot
+}
+
+/// Limiter shared by the server, the tools and the library clients.
+pub fn global() -> Arc<ConcurrencyLimiter> {
+    GLOBAL
+        .get_or_init(|| Arc::new(ConcurrencyLimiter::new(ConcurrencyConfig::from_env())))
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_zone: usize, queue_timeout: Duration) -> Arc<ConcurrencyLimiter> {
+        Arc::new(ConcurrencyLimiter::new(ConcurrencyConfig {
+            default_per_zone: Some(per_zone),
+            queue_timeout,
+            ..ConcurrencyConfig::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn waiting_callers_get_slots_in_arrival_order() {
+        let limiter = limiter(1, Duration::from_secs(5));
+        let held = limiter.acquire("z", "https://example.com/", None).await.unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for i in 0..3 {
+            let (limiter, order) = (limiter.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _permit = limiter.acquire("z", "https://example.com/", None).await.unwrap();
+                order.lock().unwrap().push(i);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }));
+            // Let each caller join the queue before the next one arrives.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let zone = limiter.stats().into_iter().find(|s| s.scope == "zone:z").unwrap();
+        assert_eq!((zone.in_flight, zone.waiting), (1, 3));
+
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn callers_give_up_after_the_queue_timeout_or_their_deadline() {
+        let limiter = limiter(1, Duration::from_millis(50));
+        let _held = limiter.acquire("z", "https://example.com/", None).await.unwrap();
+
+        let started = Instant::now();
+        match limiter.acquire("z", "https://example.com/", None).await {
+            Err(BrightDataError::QueueTimeout { scope, waited }) => {
+                assert_eq!((scope.as_str(), waited), ("zone:z", Duration::from_millis(50)));
+            }
+            other => panic!("expected a queue timeout, got {:?}", other),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // A sooner call deadline cuts the wait short.
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let err = limiter.acquire("z", "https://example.com/", Some(deadline)).await.unwrap_err();
+        assert!(matches!(err, BrightDataError::QueueTimeout { waited, .. } if waited <= Duration::from_millis(10)));
+        // Other zones are not held up.
+        limiter.acquire("other", "https://example.com/", None).await.unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+// src/profile.rs
+//! Named Bright Data accounts ("profiles").
+//!
+//! The unnamed `default` profile comes from the top-level variables
+//! (`API_TOKEN`, `WEB_UNLOCKER_ZONE`, ...). Extra profiles are listed in
+//! `BRIGHTDATA_PROFILES=prod,experiments` and configured with prefixed
+//! variables such as `PROFILE_PROD_API_TOKEN` or `PROFILE_PROD_SERP_ZONE`.
+//! Tokens also accept the `_FILE`/`_COMMAND` forms described in [`crate::secret`].
+
+use crate::config::BrightDataConfig;
+use crate::secret::Secret;
+use crate::server::BrightDataUrls;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Load the configuration of a single profile from the environment.
+/// `None` and `"default"` both resolve to the unprefixed variables; any other
+/// name must be listed in `BRIGHTDATA_PROFILES`.
+pub fn load(name: Option<&str>) -> io::Result<BrightDataConfig> {
+    let prefix = match name {
+        None => String::new(),
+        Some(n) if n == DEFAULT_PROFILE => String::new(),
+        Some(n) if list("BRIGHTDATA_PROFILES").iter().any(|p| p == n) => format!("PROFILE_{}_", env_key(n)),
+        Some(n) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown profile '{}': not listed in BRIGHTDATA_PROFILES", n),
+            ));
+        }
+    };
+    let var = |key: &str| env::var(format!("{prefix}{key}")).ok();
+
+    let mut token = Secret::from_env(&format!("{prefix}API_TOKEN"))?;
+    if token.is_none() && prefix.is_empty() {
+        token = Secret::from_env("BRIGHTDATA_API_TOKEN")?;
+    }
+
+    Ok(BrightDataConfig {
+        endpoint: var("ENDPOINT").unwrap_or_else(|| BrightDataUrls::REQUEST_API.to_string()),
+        token: token.unwrap_or_default(),
+        web_unlocker_zone: var("WEB_UNLOCKER_ZONE").unwrap_or_else(|| "default_zone".to_string()),
+        browser_zone: var("BROWSER_ZONE").unwrap_or_else(|| "default_browser".to_string()),
+        serp_zone: var("SERP_ZONE"),
+    })
+}
+
+/// Load the default profile plus every profile listed in `BRIGHTDATA_PROFILES`.
+pub fn load_all() -> io::Result<HashMap<String, BrightDataConfig>> {
+    let mut profiles = HashMap::new();
+    profiles.insert(DEFAULT_PROFILE.to_string(), load(None)?);
+
+    for name in list("BRIGHTDATA_PROFILES") {
+        let config = load(Some(&name))?;
+        profiles.insert(name, config);
+    }
+    Ok(profiles)
+}
+
+/// Parse `PROFILE_API_KEYS=team-a:prod,lab:experiments` into a map from API
+/// key name (see `auth`) to profile.
+pub fn api_keys_from_env() -> HashMap<String, String> {
+    list("PROFILE_API_KEYS")
+        .into_iter()
+        .filter_map(|pair| {
+            let (key, profile) = pair.split_once(':')?;
+            Some((key.trim().to_string(), profile.trim().to_string()))
+        })
+        .collect()
+}
+
+fn list(var: &str) -> Vec<String> {
+    env::var(var)
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn env_key(name: &str) -> String {
+    name.to_ascii_uppercase().replace(['-', '.', ' '], "_")
+}
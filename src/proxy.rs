@@ -0,0 +1,300 @@
+// src/proxy.rs
+//! Proxy-mode access to Bright Data through the super-proxy.
+//!
+//! Targeting is encoded in the proxy username, e.g.
+//! `brd-customer-hl_1234-zone-residential-country-us-city-newyork-session-abc`.
+//! HTTPS targets are tunnelled with CONNECT; zones that re-sign TLS (Web
+//! Unlocker, SERP) need Bright Data's CA certificate, set with
+//! `BRIGHTDATA_PROXY_CA_CERT=/path/to/ca.crt`.
+
+use crate::detect::BlockDetector;
+use crate::error::BrightDataError;
+use crate::hedge::Hedger;
+use crate::retry::{self, Retried, RetryPolicy};
+use crate::secret::Secret;
+use crate::session::{self, SessionPool, StickyRequest};
+use reqwest::{Client, Method, RequestBuilder, Response};
+use std::env;
+use std::future::Future;
+use std::path::PathBuf;
+
+pub const DEFAULT_PROXY_HOST: &str = "zproxy.lum-superproxy.io";
+pub const DEFAULT_PROXY_PORT: u16 = 22225;
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// Base username, `brd-customer-<id>` optionally followed by `-zone-<zone>`.
+    pub username: String,
+    pub password: Secret,
+    pub host: String,
+    pub port: u16,
+    pub ca_cert: Option<PathBuf>,
+}
+
+impl ProxyConfig {
+    pub fn from_env() -> Result<Self, BrightDataError> {
+        Ok(Self {
+            username: env::var("BRIGHTDATA_PROXY_USERNAME").unwrap_or_default(),
+            password: Secret::from_env("BRIGHTDATA_PROXY_PASSWORD")?.unwrap_or_default(),
+            host: env::var("BRIGHTDATA_PROXY_HOST").unwrap_or_else(|_| DEFAULT_PROXY_HOST.into()),
+            port: env::var("BRIGHTDATA_PROXY_PORT")
+                .ok()
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(DEFAULT_PROXY_PORT),
+            ca_cert: env::var("BRIGHTDATA_PROXY_CA_CERT").ok().map(PathBuf::from),
+        })
+    }
+}
+
+/// Per-request targeting flags appended to the proxy username.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyTarget {
+    pub zone: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub session: Option<String>,
+    pub dns_remote: bool,
+}
+
+impl ProxyTarget {
+    pub fn zone(zone: impl Into<String>) -> Self {
+        Self {
+            zone: Some(zone.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn country(mut self, country: impl Into<String>) -> Self {
+        self.country = Some(country.into().to_ascii_lowercase());
+        self
+    }
+
+    pub fn city(mut self, city: impl Into<String>) -> Self {
+        // Bright Data expects city names without spaces, e.g. `newyork`.
+        self.city = Some(city.into().to_ascii_lowercase().replace(' ', ""));
+        self
+    }
+
+    pub fn asn(mut self, asn: u32) -> Self {
+        self.asn = Some(asn);
+        self
+    }
+
+    pub fn session(mut self, session: impl Into<String>) -> Self {
+        self.session = Some(session.into());
+        self
+    }
+
+    pub fn dns_remote(mut self, dns_remote: bool) -> Self {
+        self.dns_remote = dns_remote;
+        self
+    }
+
+    /// Read the optional `zone`, `country`, `city`, `asn`, `session` and
+    /// `dns_remote` tool arguments on top of `self`.
+    pub fn with_arguments(mut self, args: &serde_json::Value) -> Self {
+        let str_arg = |key: &str| args.get(key).and_then(|v| v.as_str());
+
+        if let Some(zone) = str_arg("zone") {
+            self.zone = Some(zone.to_string());
+        }
+        if let Some(country) = str_arg("country") {
+            self = self.country(country);
+        }
+        if let Some(city) = str_arg("city") {
+            self = self.city(city);
+        }
+        if let Some(asn) = args.get("asn").and_then(|v| v.as_u64()) {
+            self.asn = u32::try_from(asn).ok();
+        }
+        if let Some(session) = str_arg("session") {
+            self.session = Some(session.to_string());
+        }
+        if let Some(dns_remote) = args.get("dns_remote").and_then(|v| v.as_bool()) {
+            self.dns_remote = dns_remote;
+        }
+        self
+    }
+
+    pub fn username(&self, base: &str) -> String {
+        let mut username = base.to_string();
+
+        if let Some(zone) = &self.zone {
+            if !base.contains("-zone-") {
+                username.push_str(&format!("-zone-{zone}"));
+            }
+        }
+        if let Some(country) = &self.country {
+            username.push_str(&format!("-country-{country}"));
+        }
+        if let Some(city) = &self.city {
+            username.push_str(&format!("-city-{city}"));
+        }
+        if let Some(asn) = self.asn {
+            username.push_str(&format!("-asn-{asn}"));
+        }
+        if let Some(session) = &self.session {
+            username.push_str(&format!("-session-{session}"));
+        }
+        if self.dns_remote {
+            username.push_str("-dns-remote");
+        }
+        username
+    }
+}
+
+/// How [`ProxyClient::fetch_unblocked`] hedges and gets past blocked pages.
+pub struct Unblock<'a> {
+    pub hedger: &'a Hedger,
+    /// Race slow fetches against a hedge on a fresh session.
+    pub hedge: bool,
+    pub detector: &'a BlockDetector,
+    /// Move to a new session when a page is blocked, up to
+    /// `detector.max_rotations` times.
+    pub rotate: bool,
+    /// Sticky session the call is pinned to; rotations replace it in `sessions`.
+    pub sticky: Option<(&'a SessionPool, &'a StickyRequest)>,
+}
+
+/// Page returned by [`ProxyClient::fetch_unblocked`].
+#[derive(Debug)]
+pub struct Unblocked {
+    pub body: String,
+    pub attempts: u32,
+    pub rotations: u32,
+    pub hedged: bool,
+    /// Session the page was fetched on.
+    pub session: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyClient {
+    config: ProxyConfig,
+}
+
+impl ProxyClient {
+    pub fn new(config: ProxyConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn from_env() -> Result<Self, BrightDataError> {
+        Ok(Self::new(ProxyConfig::from_env()?))
+    }
+
+    pub fn config(&self) -> &ProxyConfig {
+        &self.config
+    }
+
+    /// A pooled `reqwest::Client` routed through the super-proxy with the
+    /// given targeting. Clients are cached per distinct username, so targets
+    /// without a session share one connection pool.
+    pub fn client(&self, target: &ProxyTarget) -> Result<Client, BrightDataError> {
+        let proxy_url = format!(
+            "http://{}:{}@{}:{}",
+            urlencoding::encode(&target.username(&self.config.username)),
+            urlencoding::encode(self.config.password.expose()),
+            self.config.host,
+            self.config.port,
+        );
+        crate::http::proxied(&proxy_url, self.config.ca_cert.as_deref())
+    }
+
+    /// Circuit breaker key for traffic to `target` through this proxy.
+    pub fn breaker_key(&self, target: &ProxyTarget) -> String {
+        crate::breaker::key(
+            target.zone.as_deref().unwrap_or("proxy"),
+            &format!("{}:{}", self.config.host, self.config.port),
+        )
+    }
+
+    pub fn request(&self, target: &ProxyTarget, method: Method, url: &str) -> Result<RequestBuilder, BrightDataError> {
+        Ok(self.client(target)?.request(method, url))
+    }
+
+    pub async fn get(&self, target: &ProxyTarget, url: &str) -> Result<Response, BrightDataError> {
+        Ok(self.request(target, Method::GET, url)?.send().await?)
+    }
+
+    /// GET `url` through `target` with retries, under the zone's concurrency
+    /// limit and circuit breaker, and return the body of a 2xx response.
+    pub async fn fetch(&self, target: &ProxyTarget, url: &str, policy: &RetryPolicy, label: &str) -> Result<Retried<String>, BrightDataError> {
+        let client = self.client(target)?;
+        let zone = target.zone.as_deref();
+        let _permit = crate::limits::global()
+            .acquire(zone.unwrap_or_default(), url, policy.deadline)
+            .await?;
+        let Retried { value: res, attempts } = crate::breaker::global()
+            .call(&self.breaker_key(target), retry::send(policy, label, || client.get(url)))
+            .await?;
+
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = res.text().await?;
+        if !status.is_success() {
+            return Err(BrightDataError::from_response(status, &headers, &body, zone, Some(url)));
+        }
+        Ok(Retried { value: body, attempts })
+    }
+
+    /// [`fetch`](Self::fetch) `url`, hedged when `unblock.hedge` is set, and
+    /// fetch it again on a new session while the detector finds it blocked.
+    /// `admit` is asked before each hedge is sent, see [`Hedger::run`].
+    pub async fn fetch_unblocked<A, AFut>(
+        &self,
+        mut target: ProxyTarget,
+        url: &str,
+        policy: &RetryPolicy,
+        label: &str,
+        unblock: Unblock<'_>,
+        mut admit: A,
+    ) -> Result<Unblocked, BrightDataError>
+    where
+        A: FnMut() -> AFut,
+        AFut: Future<Output = Result<(), BrightDataError>>,
+    {
+        let Unblock { hedger, hedge, detector, rotate, sticky } = unblock;
+        let mut attempts = 0;
+        let mut rotations = 0;
+        let mut hedged = false;
+
+        loop {
+            let res = hedger
+                .run(hedge, &mut admit, |is_hedge| {
+                    let mut target = target.clone();
+                    // Hedges run on a fresh session so they get another exit IP.
+                    if is_hedge {
+                        target.session = Some(session::new_session_id());
+                    }
+                    async move {
+                        let res = self.fetch(&target, url, policy, label).await?;
+                        Ok((res, target.session))
+                    }
+                })
+                .await?;
+            hedged |= res.hedged;
+            let (res, session) = res.value;
+            attempts += res.attempts;
+            target.session = session;
+
+            match detector.check(url, &res.value, target.zone.as_deref()) {
+                Ok(()) => {
+                    return Ok(Unblocked { body: res.value, attempts, rotations, hedged, session: target.session });
+                }
+                Err(blocked)
+                    if rotate
+                        && rotations < detector.max_rotations
+                        && !policy.deadline.is_some_and(crate::deadline::is_expired) =>
+                {
+                    rotations += 1;
+                    log::info!("{}: {}, rotating session ({}/{})", label, blocked, rotations, detector.max_rotations);
+                    target.session = Some(match sticky {
+                        Some((sessions, req)) => sessions.rotate(&req.name, req.ttl).await,
+                        None => session::new_session_id(),
+                    });
+                }
+                Err(blocked) => return Err(blocked),
+            }
+        }
+    }
+}
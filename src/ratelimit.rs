@@ -0,0 +1,916 @@
+// src/ratelimit.rs
+//! Call rate limits for the MCP server.
+//!
+//! Limits come from a spec such as
+//! `scrape_website=100/1m,search_web=30/1m,zone:serp=500/1h,*=200/1h`:
+//! a tool name limits each caller's calls to that tool, `client=60/1m` limits
+//! each caller's calls overall, `client:<id>=600/1m` overrides that for one
+//! caller (ids as produced by `identity::client_id`, e.g. `ip:10.0.0.7`),
+//! `zone:<name>` limits requests sent to a zone and `*` limits all calls
+//! together. Periods are `<n><unit>` with unit `s`, `m`, `h` or `d` (`1m` can
+//! be written `m`). An optional `:<n>` sets the burst, e.g. `100/1m:20`; it
+//! defaults to the limit.
+//!
+//! The spec is read from `RATE_LIMIT`, or from the file named by
+//! `RATE_LIMIT_FILE`, which is re-read when it changes so limits can be
+//! adjusted without a restart.
+//!
+//! Calls over a limit are rejected, or wait for a slot when the caller passes
+//! `rate_limit_wait_ms` or the server runs with `RATE_LIMIT_MODE=queue`; waits
+//! are capped by `RATE_LIMIT_MAX_WAIT_MS` and the call deadline. Waiting calls
+//! queue per caller, and per zone; a call that does not wait is
+//! refused while others are queued ahead of it.
+//!
+//! Buckets are kept in this process unless the limiter is given a shared
+//! [`StateStore`], so that servers sharing a store share their limits.
+
+use crate::error::BrightDataError;
+use crate::store::{self, StateStore};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Limits used when no spec is configured.
+pub const DEFAULT_SPEC: &str = "scrape_website=10/1m,search_web=10/1m,extract_data=10/1m";
+
+/// Tool argument asking to wait this many milliseconds for a rate-limit slot.
+pub const WAIT_ARG: &str = "rate_limit_wait_ms";
+
+/// How often `RATE_LIMIT_FILE` is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+const SHARDS: usize = 16;
+/// Shard size at which buckets that are full again are first swept out.
+const MIN_SWEEP: usize = 256;
+
+/// Store key prefix of rate-limit buckets.
+const KEY_PREFIX: &str = "rate:";
+
+/// The wait for a rate-limit slot requested in `args`, if any.
+pub fn requested_wait(args: &serde_json::Value) -> Option<Duration> {
+    args.get(WAIT_ARG).and_then(|v| v.as_u64()).map(Duration::from_millis)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LimitScope {
+    Global,
+    Tool(String),
+    Zone(String),
+    /// One caller (see `identity`); `*` is the default for every caller.
+    Client(String),
+}
+
+impl LimitScope {
+    /// Parse a rule key: `*`, `client`, `client:<id>`, `zone:<name>` or a tool.
+    pub fn parse(key: &str) -> Self {
+        match key.trim() {
+            "*" => LimitScope::Global,
+            "client" => LimitScope::Client("*".to_string()),
+            key => {
+                if let Some(zone) = key.strip_prefix("zone:") {
+                    LimitScope::Zone(zone.to_string())
+                } else if let Some(client) = key.strip_prefix("client:") {
+                    LimitScope::Client(client.to_string())
+                } else {
+                    LimitScope::Tool(key.to_string())
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for LimitScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitScope::Global => write!(f, "*"),
+            LimitScope::Tool(tool) => write!(f, "{}", tool),
+            LimitScope::Zone(zone) => write!(f, "zone:{}", zone),
+            LimitScope::Client(client) => write!(f, "client:{}", client),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub limit: u32,
+    pub period: Duration,
+    /// Calls allowed back to back after an idle period.
+    pub burst: u32,
+}
+
+impl Rate {
+    /// Parse `100/1m`, or `100/1m:20` for a burst of 20 (defaults to the limit).
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (limit, period) = text
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| format!("Invalid rate '{}', expected <count>/<period>", text))?;
+        let limit: u32 = limit
+            .trim()
+            .parse()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("Invalid count in rate '{}'", text))?;
+        let (period, burst) = match period.split_once(':') {
+            Some((period, burst)) => {
+                let burst = burst
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("Invalid burst in rate '{}'", text))?;
+                (period, burst)
+            }
+            None => (period, limit),
+        };
+        let rate = Self { limit, period: parse_period(period.trim())?, burst };
+        // Buckets hold nanoseconds in a u64; a full bucket must fit.
+        if rate.interval().as_nanos() * burst as u128 > u64::MAX as u128 {
+            return Err(format!("Period in rate '{}' is too long", text));
+        }
+        Ok(rate)
+    }
+
+    /// Time one call adds to the bucket.
+    fn interval(&self) -> Duration {
+        self.period / self.limit
+    }
+}
+
+fn parse_period(text: &str) -> Result<Duration, String> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (count, unit) = text.split_at(split);
+    let count: u64 = if count.is_empty() { 1 } else { count.parse().map_err(|_| format!("Invalid period '{}'", text))? };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("Invalid period '{}', expected s, m, h or d", text)),
+    };
+    match count.checked_mul(secs) {
+        Some(0) => Err(format!("Empty period '{}'", text)),
+        Some(total) => Ok(Duration::from_secs(total)),
+        None => Err(format!("Period '{}' is too long", text)),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitSpec {
+    pub rules: HashMap<LimitScope, Rate>,
+}
+
+impl RateLimitSpec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut rules = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (key, rate) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid rate limit '{}', expected <key>=<count>/<period>", entry))?;
+            rules.insert(LimitScope::parse(key), Rate::parse(rate)?);
+        }
+        Ok(Self { rules })
+    }
+
+    /// `RATE_LIMIT`, falling back to [`DEFAULT_SPEC`].
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(&std::env::var("RATE_LIMIT").unwrap_or_else(|_| DEFAULT_SPEC.to_string()))
+    }
+
+    /// Read a spec file: one rule per line or comma separated, `#` comments.
+    pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let spec = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(",");
+        Self::parse(&spec).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// The rule for `scope`; callers without an override get `client:*`.
+    pub fn rate(&self, scope: &LimitScope) -> Option<Rate> {
+        let rate = self.rules.get(scope).copied();
+        match scope {
+            LimitScope::Client(_) => rate.or_else(|| self.rules.get(&LimitScope::Client("*".to_string())).copied()),
+            _ => rate,
+        }
+    }
+}
+
+/// Counts a call as waiting until it is dropped, also when it is cancelled.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn enter(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Where a caller stands against its tightest limit after a call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    /// Rule key, e.g. `scrape_website` or `client:ip:10.0.0.7`.
+    pub key: String,
+    /// Calls the bucket holds when full, the rule's burst.
+    pub limit: u32,
+    /// Calls that can still be made back to back.
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+}
+
+#[derive(Debug)]
+struct Source {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// A rule's scope plus the caller it is counted for (empty when shared).
+type BucketKey = (LimitScope, String);
+
+fn store_key(key: &BucketKey) -> String {
+    format!("{}{}|{}", KEY_PREFIX, key.0, key.1)
+}
+
+/// GCRA state for one bucket: the theoretical arrival time of the next call,
+/// in nanoseconds since the limiter's epoch.
+type Shard = RwLock<HashMap<BucketKey, AtomicU64>>;
+
+/// Where bucket state is kept.
+#[derive(Debug)]
+enum Buckets {
+    /// In this process. Keys are spread over shards; updates are a
+    /// compare-and-swap under the shard's read lock, so concurrent calls only
+    /// contend when a new key is inserted.
+    Local { shards: Vec<Shard>, sweep_at: Vec<AtomicUsize> },
+    /// In a store shared with other processes. Each bucket is one entry
+    /// holding its theoretical arrival time in Unix nanoseconds; it expires
+    /// once that time has passed, as a full bucket needs no state.
+    Shared(Arc<dyn StateStore>),
+}
+
+/// One GCRA step for a bucket at `tat`: the new arrival time if a cell is
+/// free at `now`, or else the nanoseconds until one is.
+fn step(rate: &Rate, tat: u64, now: u64) -> Result<u64, u64> {
+    let interval = rate.interval().as_nanos() as u64;
+    let tolerance = interval * rate.burst as u64;
+    // Shared buckets count from the Unix epoch, so a long period can take the
+    // arrival time past u64::MAX.
+    let next = u128::from(tat.max(now)) + u128::from(interval);
+    let excess = (next - u128::from(now)).saturating_sub(u128::from(tolerance));
+    match u64::try_from(next) {
+        Ok(next) if excess == 0 => Ok(next),
+        Ok(_) => Err(excess as u64),
+        // A bucket that cannot be stored stays full for at least an interval.
+        Err(_) => Err(u64::try_from(excess).unwrap_or(u64::MAX).max(interval)),
+    }
+}
+
+fn status(key: &BucketKey, rate: &Rate, tat: u64, now: u64) -> RateLimitStatus {
+    let interval = rate.interval().as_nanos() as u64;
+    let used = tat - now;
+    RateLimitStatus {
+        key: key.0.to_string(),
+        limit: rate.burst,
+        remaining: ((interval * rate.burst as u64).saturating_sub(used) / interval.max(1)) as u32,
+        reset: Duration::from_nanos(used),
+    }
+}
+
+/// Rate limiter using the generic cell rate algorithm (a token bucket that
+/// stores one timestamp per key).
+#[derive(Debug)]
+pub struct RateLimiter {
+    spec: RwLock<Arc<RateLimitSpec>>,
+    source: Option<Mutex<Source>>,
+    next_reload: AtomicU64,
+    epoch: Instant,
+    buckets: Buckets,
+    /// FIFO queues of calls waiting on a limit: one per caller, and one per
+    /// zone.
+    queues: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    waiting: AtomicUsize,
+}
+
+impl RateLimiter {
+    pub fn new(spec: RateLimitSpec) -> Self {
+        Self {
+            spec: RwLock::new(Arc::new(spec)),
+            source: None,
+            next_reload: AtomicU64::new(0),
+            epoch: Instant::now(),
+            buckets: Buckets::Local {
+                shards: (0..SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+                sweep_at: (0..SHARDS).map(|_| AtomicUsize::new(MIN_SWEEP)).collect(),
+            },
+            queues: Mutex::new(HashMap::new()),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Keep bucket state in `store` when it is shared with other processes.
+    /// For a store local to this process the in-process buckets are used.
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        if store.is_shared() {
+            self.buckets = Buckets::Shared(store);
+        }
+        self
+    }
+
+    /// Reload the rules from `path` whenever it changes.
+    pub fn watch(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        self.source = Some(Mutex::new(Source { path, modified }));
+        self
+    }
+
+    pub fn spec(&self) -> Arc<RateLimitSpec> {
+        self.spec.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the rules. Bucket state is kept.
+    pub fn set_spec(&self, spec: RateLimitSpec) {
+        *self.spec.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(spec);
+    }
+
+    /// Re-read the spec file if it changed since it was last loaded. Returns
+    /// whether new rules were applied; a file that fails to parse keeps the
+    /// current rules.
+    pub fn reload(&self) -> std::io::Result<bool> {
+        let Some(source) = &self.source else { return Ok(false) };
+        let mut source = source.lock().unwrap_or_else(|e| e.into_inner());
+
+        let modified = std::fs::metadata(&source.path)?.modified().ok();
+        if modified.is_some() && modified == source.modified {
+            return Ok(false);
+        }
+        let spec = RateLimitSpec::from_file(&source.path)?;
+        source.modified = modified;
+        log::info!("Reloaded rate limits from {}", source.path.display());
+        self.set_spec(spec);
+        Ok(true)
+    }
+
+    fn reload_if_due(&self, now: u64) {
+        if self.source.is_none() {
+            return;
+        }
+        let due = self.next_reload.load(Ordering::Relaxed);
+        let next = now + RELOAD_INTERVAL.as_nanos() as u64;
+        if now >= due && self.next_reload.compare_exchange(due, next, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            if let Err(e) = self.reload() {
+                log::warn!("Keeping current rate limits: {}", e);
+            }
+        }
+    }
+
+    /// Count one call to `tool` by `client` against the client, tool and
+    /// global limits, without waiting.
+    pub async fn check_call(&self, tool: &str, client: &str) -> Result<Option<RateLimitStatus>, BrightDataError> {
+        self.wait_call(tool, client, Duration::ZERO).await
+    }
+
+    /// Like [`check_call`](Self::check_call), but wait up to `max_wait` for a
+    /// slot instead of failing. A caller's waiting calls are served in arrival
+    /// order, whatever tool they are for, and calls that do not wait are refused while others
+    /// are queued; the rate-limit error is only returned when the next slot is
+    /// further away than what is left of `max_wait`.
+    pub async fn wait_call(&self, tool: &str, client: &str, max_wait: Duration) -> Result<Option<RateLimitStatus>, BrightDataError> {
+        let buckets = [
+            (LimitScope::Client(client.to_string()), String::new()),
+            (LimitScope::Global, String::new()),
+            (LimitScope::Tool(tool.to_string()), client.to_string()),
+        ];
+        self.wait(format!("call:{}", client), &buckets, max_wait).await
+    }
+
+    /// Count one upstream request to `zone` against its limit, waiting up to
+    /// `max_wait` for a slot like [`wait_call`](Self::wait_call).
+    pub async fn wait_zone(&self, zone: &str, max_wait: Duration) -> Result<(), BrightDataError> {
+        let buckets = [(LimitScope::Zone(zone.to_string()), String::new())];
+        self.wait(format!("zone:{}", zone), &buckets, max_wait).await.map(|_| ())
+    }
+
+    /// Check `buckets` in the FIFO queue named `queue`, waiting up to
+    /// `max_wait` for a slot.
+    async fn wait(&self, queue: String, buckets: &[BucketKey], max_wait: Duration) -> Result<Option<RateLimitStatus>, BrightDataError> {
+        if max_wait.is_zero() {
+            // Queued calls are owed the next slots.
+            if let Some(err) = self.queued(&queue, buckets).await? {
+                return Err(err);
+            }
+            return self.check(buckets).await;
+        }
+        let deadline = Instant::now() + max_wait;
+        let turn_queue = self.queue(&queue);
+
+        let _waiting = Waiting::enter(&self.waiting);
+        let turn = tokio::time::timeout(max_wait, turn_queue.clone().lock_owned()).await;
+        let result = match turn {
+            // Still behind other calls in this queue, which keep their place.
+            Err(_) => match self.queued(&queue, buckets).await {
+                Ok(Some(err)) | Err(err) => Err(err),
+                Ok(None) => self.check(buckets).await,
+            },
+            Ok(_turn) => loop {
+                match self.check(buckets).await {
+                    Err(BrightDataError::RateLimitExceeded { retry_after, .. })
+                        if Instant::now() + retry_after <= deadline =>
+                    {
+                        tokio::time::sleep(retry_after).await;
+                    }
+                    result => break result,
+                }
+            },
+        };
+
+        drop(turn_queue);
+        self.queues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, queue| Arc::strong_count(queue) > 1);
+        result
+    }
+
+    fn queue(&self, name: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.queues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// The error for a call that would jump ahead of calls waiting in
+    /// `queue`, or `None` if nobody waits there. It names the bucket that
+    /// holds the call back: the one with the longest wait, or while every
+    /// bucket still has room, the one with the fewest calls left, which the
+    /// queued calls are owed and which frees a cell one interval later.
+    async fn queued(&self, queue: &str, buckets: &[BucketKey]) -> Result<Option<BrightDataError>, BrightDataError> {
+        let busy = self
+            .queues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(queue)
+            .is_some_and(|queue| Arc::strong_count(queue) > 1);
+        if !busy {
+            return Ok(None);
+        }
+        let spec = self.spec();
+        let limited: Vec<(BucketKey, Rate)> = buckets
+            .iter()
+            .filter_map(|key| spec.rate(&key.0).map(|rate| (key.clone(), rate)))
+            .collect();
+        if limited.is_empty() {
+            return Ok(None);
+        }
+
+        let (now, tats) = self.peek(&limited).await?;
+        let waits = limited.iter().zip(&tats).map(|((_, rate), tat)| step(rate, *tat, now).err().unwrap_or(0));
+        let (i, wait) = waits.enumerate().max_by_key(|(_, wait)| *wait).unwrap_or_default();
+        let (i, retry_after) = match wait {
+            0 => {
+                let i = (0..limited.len())
+                    .min_by_key(|&i| status(&limited[i].0, &limited[i].1, tats[i].max(now), now).remaining)
+                    .unwrap_or_default();
+                (i, limited[i].1.interval())
+            }
+            wait => (i, Duration::from_nanos(wait)),
+        };
+        Ok(Some(BrightDataError::RateLimitExceeded {
+            scope: limited[i].0 .0.to_string(),
+            limit: limited[i].1.burst,
+            retry_after,
+        }))
+    }
+
+    /// Current arrival times of `limited`, without taking a cell, and the
+    /// time they are measured against.
+    async fn peek(&self, limited: &[(BucketKey, Rate)]) -> Result<(u64, Vec<u64>), BrightDataError> {
+        match &self.buckets {
+            Buckets::Local { shards, .. } => {
+                let now = self.epoch.elapsed().as_nanos() as u64;
+                let tats = limited
+                    .iter()
+                    .map(|(key, _)| {
+                        let shard = shards[Self::shard(key)].read().unwrap_or_else(|e| e.into_inner());
+                        shard.get(key).map(|tat| tat.load(Ordering::Acquire)).unwrap_or(0)
+                    })
+                    .collect();
+                Ok((now, tats))
+            }
+            Buckets::Shared(store) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+                let keys: Vec<String> = limited.iter().map(|(key, _)| store_key(key)).collect();
+                store::blocking(store, move |store| {
+                    let tats = keys
+                        .iter()
+                        .map(|key| Ok(store.get(key)?.and_then(|v| v.parse().ok()).unwrap_or(0)))
+                        .collect::<Result<Vec<u64>, BrightDataError>>()?;
+                    Ok((now, tats))
+                })
+                .await
+            }
+        }
+    }
+
+    /// Calls currently waiting for a rate-limit slot.
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Count one upstream request to `zone` against its limit, without waiting.
+    pub async fn check_zone(&self, zone: &str) -> Result<(), BrightDataError> {
+        self.wait_zone(zone, Duration::ZERO).await
+    }
+
+    /// Count one hit against every limited scope, or none if any is full.
+    /// Returns the state of the scope with the fewest calls left.
+    async fn check(&self, buckets: &[BucketKey]) -> Result<Option<RateLimitStatus>, BrightDataError> {
+        self.reload_if_due(self.epoch.elapsed().as_nanos() as u64);
+        let spec = self.spec();
+
+        let limited: Vec<(BucketKey, Rate)> = buckets
+            .iter()
+            .filter_map(|key| spec.rate(&key.0).map(|rate| (key.clone(), rate)))
+            .collect();
+        if limited.is_empty() {
+            return Ok(None);
+        }
+
+        let (limited, now, outcome) = match &self.buckets {
+            Buckets::Local { .. } => {
+                let now = self.epoch.elapsed().as_nanos() as u64;
+                let outcome = self.check_local(&limited, now);
+                (limited, now, outcome)
+            }
+            Buckets::Shared(store) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+                store::blocking(store, move |store| {
+                    let outcome = check_shared(store, &limited, now)?;
+                    Ok((limited, now, outcome))
+                })
+                .await?
+            }
+        };
+
+        match outcome {
+            Ok(tats) => Ok(limited
+                .iter()
+                .zip(tats)
+                .map(|((key, rate), tat)| status(key, rate, tat, now))
+                .min_by_key(|status| status.remaining)),
+            Err((i, wait)) => Err(BrightDataError::RateLimitExceeded {
+                scope: limited[i].0 .0.to_string(),
+                limit: limited[i].1.burst,
+                retry_after: Duration::from_nanos(wait),
+            }),
+        }
+    }
+
+    /// Take a cell from every bucket, giving back the ones taken if a later
+    /// bucket is full. Returns the new arrival times, or the index of the
+    /// full bucket and the wait for it.
+    fn check_local(&self, limited: &[(BucketKey, Rate)], now: u64) -> Result<Vec<u64>, (usize, u64)> {
+        let mut tats = Vec::with_capacity(limited.len());
+        for (i, (key, rate)) in limited.iter().enumerate() {
+            match self.acquire(key, rate, now) {
+                Ok(tat) => tats.push(tat),
+                Err(wait) => {
+                    for (key, rate) in &limited[..i] {
+                        self.release(key, rate);
+                    }
+                    return Err((i, wait));
+                }
+            }
+        }
+        Ok(tats)
+    }
+
+    fn shard(key: &BucketKey) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % SHARDS
+    }
+
+    /// Take one cell from a local bucket, or return how long until one is
+    /// free. On success returns the bucket's new theoretical arrival time.
+    fn acquire(&self, key: &BucketKey, rate: &Rate, now: u64) -> Result<u64, u64> {
+        let Buckets::Local { shards, sweep_at } = &self.buckets else { unreachable!("local bucket on a shared store") };
+        let index = Self::shard(key);
+        {
+            let shard = shards[index].read().unwrap_or_else(|e| e.into_inner());
+            if let Some(tat) = shard.get(key) {
+                let mut current = tat.load(Ordering::Acquire);
+                loop {
+                    let next = step(rate, current, now)?;
+                    match tat.compare_exchange_weak(current, next, Ordering::AcqRel, Ordering::Acquire) {
+                        Ok(_) => return Ok(next),
+                        Err(actual) => current = actual,
+                    }
+                }
+            }
+        }
+
+        let mut shard = shards[index].write().unwrap_or_else(|e| e.into_inner());
+        if shard.len() >= sweep_at[index].load(Ordering::Relaxed) {
+            // Buckets whose arrival time has passed are full again, which is
+            // the same as not being tracked at all.
+            shard.retain(|_, tat| tat.load(Ordering::Relaxed) > now);
+            sweep_at[index].store((shard.len() * 2).max(MIN_SWEEP), Ordering::Relaxed);
+        }
+        let tat = shard.entry(key.clone()).or_insert_with(|| AtomicU64::new(0));
+        let next = step(rate, *tat.get_mut(), now)?;
+        *tat.get_mut() = next;
+        Ok(next)
+    }
+
+    /// Give back a cell taken by `acquire` when another scope rejected the call.
+    fn release(&self, key: &BucketKey, rate: &Rate) {
+        let Buckets::Local { shards, .. } = &self.buckets else { return };
+        let interval = rate.interval().as_nanos() as u64;
+        let shard = shards[Self::shard(key)].read().unwrap_or_else(|e| e.into_inner());
+        if let Some(tat) = shard.get(key) {
+            let _ = tat.fetch_update(Ordering::AcqRel, Ordering::Acquire, |t| Some(t.saturating_sub(interval)));
+        }
+    }
+
+    /// Number of buckets currently tracked.
+    pub async fn tracked_keys(&self) -> usize {
+        match &self.buckets {
+            Buckets::Local { shards, .. } => {
+                shards.iter().map(|shard| shard.read().unwrap_or_else(|e| e.into_inner()).len()).sum()
+            }
+            Buckets::Shared(store) => store::blocking(store, |store| store.scan(KEY_PREFIX))
+                .await
+                .map(|entries| entries.len())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Take a cell from every bucket in one store transaction, or from none if
+/// any is full. On failure reports the bucket with the longest wait, as the
+/// call needs all of them.
+fn check_shared(
+    store: &dyn StateStore,
+    limited: &[(BucketKey, Rate)],
+    now: u64,
+) -> Result<Result<Vec<u64>, (usize, u64)>, BrightDataError> {
+    let keys: Vec<String> = limited.iter().map(|(key, _)| store_key(key)).collect();
+    let mut outcome = Ok(Vec::new());
+    store.update_many(&keys, &mut |current| {
+        let steps: Vec<Result<u64, u64>> = limited
+            .iter()
+            .zip(current)
+            .map(|((_, rate), tat)| step(rate, tat.as_deref().and_then(|v| v.parse().ok()).unwrap_or(0), now))
+            .collect();
+        if let Some(full) = steps.iter().enumerate().filter_map(|(i, s)| s.err().map(|wait| (i, wait))).max_by_key(|(_, wait)| *wait) {
+            outcome = Err(full);
+            return None;
+        }
+        let tats: Vec<u64> = steps.into_iter().flatten().collect();
+        let updates = tats.iter().map(|tat| Some((tat.to_string(), Some(Duration::from_nanos(tat - now))))).collect();
+        outcome = Ok(tats);
+        Some(updates)
+    })?;
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{Entry, MemoryStore, Update, Updates};
+
+    const MS: u64 = 1_000_000;
+
+    /// A memory store that claims to be shared, so limiters use the store path.
+    #[derive(Debug, Default)]
+    struct SharedMemory(MemoryStore);
+
+    impl StateStore for SharedMemory {
+        fn get(&self, key: &str) -> Result<Option<String>, BrightDataError> {
+            self.0.get(key)
+        }
+
+        fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), BrightDataError> {
+            self.0.set(key, value, ttl)
+        }
+
+        fn delete(&self, key: &str) -> Result<bool, BrightDataError> {
+            self.0.delete(key)
+        }
+
+        fn update(&self, key: &str, update: &mut dyn FnMut(Option<&str>) -> Update) -> Result<(), BrightDataError> {
+            self.0.update(key, update)
+        }
+
+        fn update_many(
+            &self,
+            keys: &[String],
+            update: &mut dyn FnMut(&[Option<String>]) -> Updates,
+        ) -> Result<(), BrightDataError> {
+            self.0.update_many(keys, update)
+        }
+
+        fn scan(&self, prefix: &str) -> Result<Vec<Entry>, BrightDataError> {
+            self.0.scan(prefix)
+        }
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(
+            Rate::parse("100/1m").unwrap(),
+            Rate { limit: 100, period: Duration::from_secs(60), burst: 100 }
+        );
+        assert_eq!(
+            Rate::parse(" 30/m:5 ").unwrap(),
+            Rate { limit: 30, period: Duration::from_secs(60), burst: 5 }
+        );
+        assert_eq!(Rate::parse("1/2d").unwrap().period, Duration::from_secs(2 * 86400));
+        for bad in ["100", "0/1m", "x/1m", "1/1w", "1/0s", "1/1m:0", "1/1m:x", "1/99999999999999999999d"] {
+            assert!(Rate::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn periods_that_overflow_are_rejected() {
+        assert_eq!(parse_period("h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_period(&format!("{}d", u64::MAX / 86400 + 1)).is_err());
+        // Fits in seconds, but not a bucket in nanoseconds.
+        assert!(Rate::parse("1/999999999d").is_err());
+    }
+
+    #[test]
+    fn gcra_steps_near_the_end_of_unix_time_do_not_overflow() {
+        // About 274 years per call, with room for two.
+        let rate = Rate::parse("1/100000d:2").unwrap();
+        let interval = rate.interval().as_nanos() as u64;
+        let now = 1_700_000_000_000_000_000;
+
+        let tat = step(&rate, 0, now).unwrap();
+        assert_eq!(tat, now + interval);
+        // The second cell would end past u64::MAX, so the bucket is full.
+        let wait = step(&rate, tat, now).unwrap_err();
+        assert!(wait >= interval);
+        assert_eq!(status(&(LimitScope::Global, String::new()), &rate, tat, now).remaining, 1);
+    }
+
+    #[test]
+    fn gcra_allows_the_burst_then_spaces_calls() {
+        let rate = Rate::parse("2/1s").unwrap();
+        assert_eq!(step(&rate, 0, 0), Ok(500 * MS));
+        assert_eq!(step(&rate, 500 * MS, 0), Ok(1000 * MS));
+        assert_eq!(step(&rate, 1000 * MS, 0), Err(500 * MS));
+        // Half a second later one cell has been refilled.
+        assert_eq!(step(&rate, 1000 * MS, 500 * MS), Ok(1500 * MS));
+
+        let key = (LimitScope::Global, String::new());
+        assert_eq!(
+            status(&key, &rate, 500 * MS, 0),
+            RateLimitStatus { key: "*".to_string(), limit: 2, remaining: 1, reset: Duration::from_millis(500) }
+        );
+        assert_eq!(status(&key, &rate, 1000 * MS, 0).remaining, 0);
+    }
+
+    #[test]
+    fn sub_millisecond_intervals_keep_the_burst() {
+        // 600µs per call: rounding the interval to milliseconds would let
+        // every call through.
+        let rate = Rate::parse("100000/1m:5").unwrap();
+        let mut tat = 0;
+        let allowed = (0..20).take_while(|_| step(&rate, tat, 0).map(|next| tat = next).is_ok()).count();
+        assert_eq!(allowed, 5);
+    }
+
+    #[tokio::test]
+    async fn limiter_enforces_a_sub_millisecond_burst() {
+        let limiter = RateLimiter::new(RateLimitSpec::parse("*=100000/1m:5").unwrap());
+        let mut allowed = 0;
+        for _ in 0..20 {
+            if limiter.check_call("scrape_website", "c").await.is_ok() {
+                allowed += 1;
+            }
+        }
+        assert_eq!(allowed, 5);
+    }
+
+    #[tokio::test]
+    async fn status_reports_limit_and_remaining_in_burst_calls() {
+        let limiter = RateLimiter::new(RateLimitSpec::parse("*=100/1m:5").unwrap());
+        let status = limiter.check_call("scrape_website", "c").await.unwrap().unwrap();
+        assert_eq!((status.limit, status.remaining), (5, 4));
+    }
+
+    #[tokio::test]
+    async fn calls_behind_a_queue_are_refused_with_the_blocking_bucket() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitSpec::parse("client=100/1s,search_web=1/1s").unwrap()));
+        limiter.check_call("search_web", "c").await.unwrap();
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.wait_call("search_web", "c", Duration::from_secs(2)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        match limiter.check_call("search_web", "c").await {
+            Err(BrightDataError::RateLimitExceeded { scope, limit, retry_after }) => {
+                assert_eq!((scope.as_str(), limit), ("search_web", 1));
+                assert!(retry_after > Duration::from_millis(500), "{:?}", retry_after);
+            }
+            other => panic!("expected a rate limit error, got {:?}", other),
+        }
+        assert!(queued.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_callers_calls_queue_behind_each_other_across_tools() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitSpec::parse("client=100/1s,search_web=1/1s").unwrap()));
+        limiter.check_call("search_web", "c").await.unwrap();
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.wait_call("search_web", "c", Duration::from_secs(2)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The caller's scrape does not jump ahead of its queued search...
+        assert!(limiter.check_call("scrape_website", "c").await.is_err());
+        // ...but other callers are not held up.
+        limiter.check_call("scrape_website", "d").await.unwrap();
+        assert!(queued.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejected_calls_give_back_earlier_cells() {
+        let limiter = RateLimiter::new(RateLimitSpec::parse("client=5/1m,search_web=1/1m").unwrap());
+        limiter.check_call("search_web", "c").await.unwrap();
+        assert!(limiter.check_call("search_web", "c").await.is_err());
+        // The rejected search did not use up one of the client's calls.
+        for _ in 0..4 {
+            limiter.check_call("scrape_website", "c").await.unwrap();
+        }
+        assert!(limiter.check_call("scrape_website", "c").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shared_store_counts_calls_of_every_limiter() {
+        let store: Arc<dyn StateStore> = Arc::new(SharedMemory::default());
+        let spec = RateLimitSpec::parse("*=3/1m").unwrap();
+        let first = RateLimiter::new(spec.clone()).with_store(store.clone());
+        let second = RateLimiter::new(spec).with_store(store);
+
+        first.check_call("search_web", "a").await.unwrap();
+        second.check_call("search_web", "b").await.unwrap();
+        let status = first.check_call("search_web", "c").await.unwrap().unwrap();
+        assert_eq!(status.remaining, 0);
+        let err = second.check_call("search_web", "d").await.unwrap_err();
+        assert!(matches!(err, BrightDataError::RateLimitExceeded { limit: 3, .. }));
+        assert_eq!(first.tracked_keys().await, 1);
+    }
+
+    #[tokio::test]
+    async fn local_stores_keep_buckets_in_process() {
+        let limiter = RateLimiter::new(RateLimitSpec::parse("*=1/1m").unwrap()).with_store(Arc::new(MemoryStore::new()));
+        assert!(matches!(limiter.buckets, Buckets::Local { .. }));
+    }
+
+    #[test]
+    fn reload_applies_changed_files_and_keeps_rules_on_errors() {
+        let path = std::env::temp_dir().join(format!("snm-rate-limit-test-{}.txt", std::process::id()));
+        let write = |text: &str, age: u64| {
+            std::fs::write(&path, text).unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(age);
+            std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        };
+        let scope = LimitScope::Tool("search_web".to_string());
+
+        write("search_web=1/1m  # tight\n", 30);
+        let limiter = RateLimiter::new(RateLimitSpec::from_file(&path).unwrap()).watch(&path);
+        assert!(!limiter.reload().unwrap());
+
+        write("search_web=10/1m\nclient=5/1s\n", 20);
+        assert!(limiter.reload().unwrap());
+        assert_eq!(limiter.spec().rate(&scope).map(|r| r.limit), Some(10));
+        assert_eq!(limiter.spec().rate(&LimitScope::Client("x".to_string())).map(|r| r.limit), Some(5));
+
+        write("search_web=oops", 10);
+        assert!(limiter.reload().is_err());
+        assert_eq!(limiter.spec().rate(&scope).map(|r| r.limit), Some(10));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,91 @@
+// src/request.rs
+//! Request building and response parsing shared by the async and blocking clients.
+
+use crate::config::BrightDataConfig;
+use crate::error::BrightDataError;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    pub engine: Option<String>,
+    pub cursor: Option<String>,
+}
+
+impl SearchRequest {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn search_payload(config: &BrightDataConfig, search: &SearchRequest) -> Value {
+    let url = build_search_url(
+        search.engine.as_deref().unwrap_or("google"),
+        &search.query,
+        search.cursor.as_deref().unwrap_or("0"),
+    );
+
+    json!({
+        "url": url,
+        "zone": config.serp_zone.as_deref().unwrap_or(&config.web_unlocker_zone),
+        "format": "raw",
+        "data_format": "markdown"
+    })
+}
+
+pub fn scrape_payload(config: &BrightDataConfig, url: &str, markdown: bool) -> Value {
+    let mut payload = json!({
+        "url": url,
+        "zone": config.web_unlocker_zone,
+        "format": "raw",
+    });
+
+    if markdown {
+        payload["data_format"] = json!("markdown");
+    }
+    payload
+}
+
+pub fn authorization(config: &BrightDataConfig) -> String {
+    format!("Bearer {}", config.token.expose())
+}
+
+/// Turn a non-2xx answer into a classified error, otherwise hand back the body.
+/// `payload` is the request sent to Bright Data; its zone and target URL are
+/// attached to the error.
+pub fn check_response(status: StatusCode, headers: &HeaderMap, body: String, payload: &Value) -> Result<String, BrightDataError> {
+    if !status.is_success() {
+        return Err(BrightDataError::from_response(
+            status,
+            headers,
+            &body,
+            payload["zone"].as_str(),
+            payload["url"].as_str(),
+        ));
+    }
+    Ok(body)
+}
+
+/// Lowercased host of `url`, without userinfo or port.
+pub fn host_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url).ok()?.host_str().map(str::to_ascii_lowercase)
+}
+
+pub fn build_search_url(engine: &str, query: &str, cursor: &str) -> String {
+    let encoded = urlencoding::encode(query);
+    let page: usize = cursor.parse().unwrap_or(0);
+    let start = page * 10;
+
+    match engine {
+        "bing" => format!("https://www.bing.com/search?q={}&first={}", encoded, start + 1),
+        "yandex" => format!("https://yandex.com/search/?text={}&p={}", encoded, page),
+        "duckduckgo" => format!("https://duckduckgo.com/?q={}&s={}", encoded, start),
+        _ => format!("https://www.google.com/search?q={}&start={}", encoded, start),
+    }
+}
@@ -0,0 +1,356 @@
+// src/retry.rs
+//! Retries with exponential backoff and full jitter.
+//!
+//! Only failures that are safe to repeat are retried: connect errors,
+//! timeouts, 429, 5xx and Bright Data proxy errors that report a transient
+//! upstream problem. Anything else is handed back to the caller unchanged.
+//!
+//! When the upstream says how long to wait (`Retry-After` or a rate-limit
+//! reset header) that wait replaces the computed backoff. If the wait would
+//! overrun the policy's `budget`, the throttled response is returned as-is so
+//! the caller can report the remaining wait.
+
+use crate::error::BrightDataError;
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::env;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Bright Data reports proxy-side failures in these headers.
+pub const BRD_ERROR_HEADERS: [&str; 2] = ["x-brd-error", "x-luminati-error"];
+
+/// Throttling headers carrying the number of seconds until the limit resets.
+const RESET_HEADERS: [&str; 3] = ["x-ratelimit-reset", "ratelimit-reset", "x-brd-ratelimit-reset"];
+
+/// Fragments of Bright Data error messages that describe transient problems.
+const TRANSIENT_BRD_ERRORS: [&str; 4] = ["timeout", "timed out", "socket", "connection"];
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Total time all attempts and waits may take; `None` means unbounded.
+    pub budget: Option<Duration>,
+    /// Absolute deadline of the call this policy is used for, see [`crate::deadline`].
+    pub deadline: Option<Instant>,
+    /// Incremented for every attempt sent with this policy, failed ones included.
+    pub counter: Option<Arc<AtomicU32>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            budget: None,
+            deadline: None,
+            counter: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Default::default()
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::new(0)
+    }
+
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let millis = |key: &str| env::var(key).ok().and_then(|v| v.parse().ok()).map(Duration::from_millis);
+
+        Self {
+            max_retries: env::var("MAX_RETRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(defaults.max_retries),
+            base_delay: millis("RETRY_BASE_DELAY_MS").unwrap_or(defaults.base_delay),
+            max_delay: millis("RETRY_MAX_DELAY_MS").unwrap_or(defaults.max_delay),
+            budget: env::var("REQUEST_TIMEOUT").ok().and_then(|v| v.parse().ok()).map(Duration::from_secs),
+            deadline: None,
+            counter: None,
+        }
+    }
+
+    /// A copy of this policy bound to the deadline requested by a tool call's
+    /// arguments, if it asked for one.
+    pub fn for_call(&self, args: &serde_json::Value) -> Self {
+        match crate::deadline::requested(args) {
+            Some(timeout) => self.with_deadline(Instant::now() + timeout),
+            None => self.clone(),
+        }
+    }
+
+    /// A copy of this policy bound to a call's deadline, which replaces the
+    /// time budget.
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        Self {
+            budget: None,
+            deadline: Some(deadline),
+            ..self.clone()
+        }
+    }
+
+    /// A copy of this policy that counts its attempts in `counter`, so callers
+    /// see them even when the request finally fails.
+    pub fn counting(&self, counter: Arc<AtomicU32>) -> Self {
+        Self {
+            counter: Some(counter),
+            ..self.clone()
+        }
+    }
+
+    /// How long to wait before the next attempt, or `None` if that wait would
+    /// not fit in the remaining budget. Without a budget, upstream hints longer
+    /// than `max_delay` are not waited for.
+    pub fn next_delay(&self, retry: u32, hint: Option<Duration>, started: Instant) -> Option<Duration> {
+        let delay = match hint {
+            Some(hint) => hint.max(self.backoff(retry)),
+            None => self.backoff(retry),
+        };
+        if let Some(deadline) = self.deadline {
+            if Instant::now() + delay >= deadline {
+                return None;
+            }
+        }
+        match self.budget {
+            Some(budget) if started.elapsed() + delay > budget => None,
+            None if delay > self.max_delay && self.deadline.is_none() => None,
+            _ => Some(delay),
+        }
+    }
+
+    /// Delay before retry number `retry` (1-based): a random value between
+    /// zero and `base_delay * 2^(retry - 1)`, capped at `max_delay`.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self.base_delay.saturating_mul(1u32 << (retry.saturating_sub(1)).min(16));
+        let ceiling = exp.min(self.max_delay);
+        let millis = ceiling.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+/// A value together with the number of attempts it took to obtain it.
+#[derive(Debug)]
+pub struct Retried<T> {
+    pub value: T,
+    pub attempts: u32,
+}
+
+pub fn is_retryable_error(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout() || err.is_request()
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+pub fn is_transient_brd_error(headers: &reqwest::header::HeaderMap) -> bool {
+    BRD_ERROR_HEADERS.iter().any(|name| {
+        headers
+            .get(*name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| {
+                let v = v.to_ascii_lowercase();
+                TRANSIENT_BRD_ERRORS.iter().any(|fragment| v.contains(fragment))
+            })
+            .unwrap_or(false)
+    })
+}
+
+/// Wait requested by the upstream through `Retry-After` (delta seconds or an
+/// HTTP date) or one of the rate-limit reset headers.
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    if let Some(value) = headers.get(reqwest::header::RETRY_AFTER).and_then(|v| v.to_str().ok()) {
+        let value = value.trim();
+        if let Ok(secs) = value.parse::<u64>() {
+            return Some(Duration::from_secs(secs));
+        }
+        if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+            let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+            return Some(wait.to_std().unwrap_or(Duration::ZERO));
+        }
+    }
+
+    RESET_HEADERS.iter().find_map(|name| {
+        headers
+            .get(*name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map(Duration::from_secs_f64)
+    })
+}
+
+/// Status and headers of a response, for either reqwest client.
+trait Attempt {
+    fn status(&self) -> StatusCode;
+    fn headers(&self) -> &reqwest::header::HeaderMap;
+}
+
+impl Attempt for Response {
+    fn status(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn headers(&self) -> &reqwest::header::HeaderMap {
+        self.headers()
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl Attempt for reqwest::blocking::Response {
+    fn status(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn headers(&self) -> &reqwest::header::HeaderMap {
+        self.headers()
+    }
+}
+
+/// What to do after an attempt: hand back the outcome or wait and retry.
+enum Step<R> {
+    Done(Result<Retried<R>, BrightDataError>),
+    Retry(Duration),
+}
+
+/// Timeout for the next attempt, or an error once the call's deadline passed.
+fn attempt_timeout(policy: &RetryPolicy, label: &str) -> Result<Option<Duration>, BrightDataError> {
+    match policy.deadline {
+        Some(deadline) if crate::deadline::is_expired(deadline) => Err(crate::deadline::exceeded(label)),
+        Some(deadline) => Ok(Some(crate::deadline::remaining(deadline))),
+        None => Ok(None),
+    }
+}
+
+/// Decide between returning the outcome of attempt number `attempt` and
+/// waiting for another one.
+fn decide<R: Attempt>(
+    policy: &RetryPolicy,
+    label: &str,
+    attempt: u32,
+    started: Instant,
+    result: Result<R, reqwest::Error>,
+) -> Step<R> {
+    if let Some(counter) = &policy.counter {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    let last = attempt > policy.max_retries;
+    let (reason, delay) = match result {
+        // The timeout was cut to the caller's deadline, so this is not the
+        // upstream being slow.
+        Err(e) if e.is_timeout() && policy.deadline.is_some_and(crate::deadline::is_expired) => {
+            return Step::Done(Err(crate::deadline::exceeded(label)));
+        }
+        Ok(res) if last || !(is_retryable_status(res.status()) || is_transient_brd_error(res.headers())) => {
+            return Step::Done(Ok(Retried { value: res, attempts: attempt }));
+        }
+        Ok(res) => match policy.next_delay(attempt, retry_after(res.headers()), started) {
+            Some(delay) => (format!("HTTP {}", res.status()), delay),
+            None => {
+                log::warn!("{}: HTTP {} and no budget left to wait, giving up", label, res.status());
+                return Step::Done(Ok(Retried { value: res, attempts: attempt }));
+            }
+        },
+        Err(e) if last || !is_retryable_error(&e) => {
+            if attempt > 1 {
+                log::warn!("{}: giving up after {} attempts", label, attempt);
+            }
+            return Step::Done(Err(e.into()));
+        }
+        Err(e) => match policy.next_delay(attempt, None, started) {
+            Some(delay) => (crate::secret::scrub(&e.to_string()), delay),
+            None => return Step::Done(Err(e.into())),
+        },
+    };
+
+    log::warn!("{}: attempt {} failed ({}), retrying in {:?}", label, attempt, reason, delay);
+    Step::Retry(delay)
+}
+
+/// Send the request produced by `make` until it succeeds, fails with a
+/// non-retryable error, or the policy runs out of retries. The final
+/// response is returned even if its status is an error, so callers keep
+/// their own status handling.
+pub async fn send<F>(policy: &RetryPolicy, label: &str, mut make: F) -> Result<Retried<Response>, BrightDataError>
+where
+    F: FnMut() -> RequestBuilder,
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let mut request = make();
+        if let Some(timeout) = attempt_timeout(policy, label)? {
+            request = request.timeout(timeout);
+        }
+        match decide(policy, label, attempt, started, request.send().await) {
+            Step::Done(result) => return result,
+            Step::Retry(delay) => tokio::time::sleep(delay).await,
+        }
+    }
+}
+
+/// Blocking counterpart of [`send`].
+#[cfg(feature = "blocking")]
+pub fn send_blocking<F>(
+    policy: &RetryPolicy,
+    label: &str,
+    mut make: F,
+) -> Result<Retried<reqwest::blocking::Response>, BrightDataError>
+where
+    F: FnMut() -> reqwest::blocking::RequestBuilder,
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let mut request = make();
+        if let Some(timeout) = attempt_timeout(policy, label)? {
+            request = request.timeout(timeout);
+        }
+        match decide(policy, label, attempt, started, request.send()) {
+            Step::Done(result) => return result,
+            Step::Retry(delay) => std::thread::sleep(delay),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline_replaces_budget() {
+        let policy = RetryPolicy { budget: Some(Duration::from_secs(1)), ..RetryPolicy::default() };
+        let started = Instant::now() - Duration::from_secs(2);
+        assert_eq!(policy.next_delay(1, Some(Duration::from_secs(1)), started), None);
+
+        let policy = policy.with_deadline(Instant::now() + Duration::from_secs(60));
+        assert_eq!(policy.next_delay(1, Some(Duration::from_secs(1)), started), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn no_retry_past_the_deadline() {
+        let policy = RetryPolicy::default().with_deadline(Instant::now() + Duration::from_millis(500));
+        assert_eq!(policy.next_delay(1, Some(Duration::from_secs(1)), Instant::now()), None);
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("retry-after", "7".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        headers.clear();
+        headers.insert("x-ratelimit-reset", "1.5".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1500)));
+    }
+}
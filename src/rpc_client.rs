@@ -1,6 +1,5 @@
 // src/rpc_client.rs
 
-use crate::types::{ToolCallRequest, ToolCallResponse};
 use crate::error::BrightDataError;
 use crate::tool::Tool;
 use serde_json::Value;
@@ -26,7 +25,7 @@ impl RpcClient {
                     .execute(parameters)
                     .await
             }
-            _ => Err(BrightDataError::ToolError(format!(
+            _ => Err(BrightDataError::InvalidParams(format!(
                 "Unknown tool: {}",
                 tool_name
             ))),
@@ -1,34 +1,142 @@
 // src/server.rs
-use actix_web::{web, HttpRequest, HttpResponse, Result};
+use actix_web::{web, HttpRequest, HttpResponse, HttpResponseBuilder, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
 use reqwest::Client;
 use uuid::Uuid;
+use crate::auth::{ApiKey, ApiKeys};
+use crate::breaker::{self, BreakerState, CircuitBreakers};
+use crate::browser::BrowserClient;
+use crate::budget::{BudgetConfig, Budgets};
+use crate::client::BrightDataClient;
+use crate::deadline;
+use crate::detect::{self, BlockDetector};
+use crate::error::BrightDataError;
+use crate::fallback::{self, FallbackChains, ZoneStep};
+use crate::hedge::{self, Hedger};
+use crate::proxy::{ProxyClient, ProxyTarget, Unblock};
+use crate::ratelimit::{self, RateLimitSpec, RateLimitStatus, RateLimiter};
+use crate::config::BrightDataConfig;
+use crate::cors::CorsConfig;
+use crate::http::{self, HttpClientConfig};
+use crate::identity::{self, TrustedProxies};
+use crate::limits::{self, ConcurrencyLimiter};
+use crate::profile;
+use crate::request::{self, SearchRequest};
+use crate::retry::{self, RetryPolicy};
+use crate::session::{SessionPool, StickyRequest};
+use crate::store::StoreConfig;
+use crate::usage::{self, UsageFilter, UsageLedger, UsageRecord};
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub api_token: String,
-    pub web_unlocker_zone: String,
-    pub browser_zone: String,
-    pub rate_limit: Option<String>,
+    pub rate_limit: RateLimitSpec,
+    pub rate_limit_file: Option<PathBuf>,
+    /// Wait for a rate-limit slot by default instead of rejecting the call.
+    pub rate_limit_queue: bool,
+    /// Longest a call may wait for a rate-limit slot.
+    pub rate_limit_max_wait: Duration,
+    pub trusted_proxies: TrustedProxies,
+    pub budget: BudgetConfig,
+    pub usage_ledger: Option<PathBuf>,
+    /// Where rate limits, budgets and sticky sessions are kept (`STATE_STORE`).
+    pub state_store: StoreConfig,
     pub timeout: Duration,
+    pub max_call_timeout: Duration,
     pub max_retries: u32,
+    pub http: HttpClientConfig,
+    pub fallback: FallbackChains,
+    pub profiles: HashMap<String, BrightDataConfig>,
+    pub default_profile: String,
+    pub profile_api_keys: HashMap<String, String>,
+    /// Keys allowed to call `/mcp`; empty refuses every call unless
+    /// `allow_unauthenticated` is set.
+    pub api_keys: ApiKeys,
+    /// Serve `/mcp` without keys when none are configured
+    /// (`ALLOW_UNAUTHENTICATED`). The server then only listens on 127.0.0.1.
+    pub allow_unauthenticated: bool,
+    pub cors: CorsConfig,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, std::io::Error> {
+        let profiles = profile::load_all()?;
+        let default_profile = env::var("DEFAULT_PROFILE").unwrap_or_else(|_| profile::DEFAULT_PROFILE.to_string());
+        if !profiles.contains_key(&default_profile) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("DEFAULT_PROFILE '{}' is not listed in BRIGHTDATA_PROFILES", default_profile),
+            ));
+        }
+
+        let rate_limit_file = env::var("RATE_LIMIT_FILE").ok().map(PathBuf::from);
+        let rate_limit = match &rate_limit_file {
+            Some(path) => RateLimitSpec::from_file(path)?,
+            None => RateLimitSpec::from_env()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        };
+
+        let api_keys = ApiKeys::from_env()?;
+        let profile_api_keys = profile::api_keys_from_env();
+        for (name, profile) in &profile_api_keys {
+            let problem = if api_keys.get(name).is_none() {
+                "is not an API key name in API_KEYS"
+            } else if !profiles.contains_key(profile) {
+                "maps to a profile not listed in BRIGHTDATA_PROFILES"
+            } else {
+                continue;
+            };
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("PROFILE_API_KEYS entry '{}' {}", name, problem),
+            ));
+        }
+
+        let allow_unauthenticated = env::var("ALLOW_UNAUTHENTICATED")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+        if api_keys.is_empty() && !allow_unauthenticated {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "No API keys configured: set API_KEYS or API_KEYS_FILE, or ALLOW_UNAUTHENTICATED=true to serve /mcp without keys on 127.0.0.1",
+            ));
+        }
+
+        let timeout = Duration::from_secs(env::var("REQUEST_TIMEOUT").unwrap_or_else(|_| "60".to_string()).parse().unwrap_or(60));
+
         Ok(Self {
-            api_token: env::var("API_TOKEN").unwrap_or_default(),
-            web_unlocker_zone: env::var("WEB_UNLOCKER_ZONE").unwrap_or_else(|_| "default_zone".to_string()),
-            browser_zone: env::var("BROWSER_ZONE").unwrap_or_else(|_| "default_browser".to_string()),
-            rate_limit: env::var("RATE_LIMIT").ok(),
-            timeout: Duration::from_secs(env::var("REQUEST_TIMEOUT").unwrap_or_else(|_| "60".to_string()).parse().unwrap_or(60)),
+            rate_limit,
+            rate_limit_file,
+            rate_limit_queue: env::var("RATE_LIMIT_MODE").map(|v| v == "queue").unwrap_or(false),
+            rate_limit_max_wait: Duration::from_millis(
+                env::var("RATE_LIMIT_MAX_WAIT_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(30_000),
+            ),
+            trusted_proxies: TrustedProxies::from_env()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            budget: BudgetConfig::from_env()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            usage_ledger: env::var("USAGE_LEDGER").ok().filter(|p| !p.is_empty()).map(PathBuf::from),
+            state_store: StoreConfig::from_env()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            timeout,
+            max_call_timeout: env::var("MAX_CALL_TIMEOUT").ok().and_then(|v| v.parse().ok()).map(Duration::from_secs).unwrap_or(timeout),
             max_retries: env::var("MAX_RETRIES").unwrap_or_else(|_| "3".to_string()).parse().unwrap_or(3),
+            http: HttpClientConfig::from_env(),
+            fallback: FallbackChains::from_env()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            profiles,
+            default_profile,
+            profile_api_keys,
+            api_keys,
+            allow_unauthenticated,
+            cors: CorsConfig::from_env()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
         })
     }
 }
@@ -38,19 +146,104 @@ pub struct AppState {
     pub config: Config,
     pub session_id: Uuid,
     pub http_client: Client,
-    pub rate_limits: Arc<RwLock<HashMap<String, (u32, DateTime<Utc>)>>>,
+    pub clients: HashMap<String, BrightDataClient>,
+    pub proxy: ProxyClient,
+    pub browser: BrowserClient,
+    pub retry: RetryPolicy,
+    pub breakers: Arc<CircuitBreakers>,
+    pub detector: Arc<BlockDetector>,
+    pub sessions: Arc<SessionPool>,
+    pub limiter: Arc<ConcurrencyLimiter>,
+    pub hedger: Arc<Hedger>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub budgets: Arc<Budgets>,
+    pub usage: Arc<UsageLedger>,
     pub start_time: DateTime<Utc>,
 }
 
 impl AppState {
-    pub fn new(config: Config) -> Self {
-        Self {
+    /// Build the server state. Fails if the configured state store cannot be
+    /// opened, rather than running with limits that are not shared.
+    pub fn new(config: Config) -> std::io::Result<Self> {
+        let mut http_config = config.http.clone();
+        http_config.timeout = config.timeout;
+        let http_client = http::init(&http_config);
+        let retry = RetryPolicy {
+            max_retries: config.max_retries,
+            budget: Some(config.timeout),
+            ..RetryPolicy::from_env()
+        };
+
+        let clients = config
+            .profiles
+            .iter()
+            .map(|(name, profile)| {
+                let client = BrightDataClient::with_http_client(profile.clone(), http_client.clone())
+                    .with_retry_policy(retry.clone());
+                (name.clone(), client)
+            })
+            .collect();
+
+        let proxy = ProxyClient::from_env().map_err(std::io::Error::other)?;
+        let browser = BrowserClient::from_env().map_err(std::io::Error::other)?;
+        let state = config
+            .state_store
+            .open()
+            .map_err(|e| std::io::Error::other(format!("Cannot open state store {}: {}", config.state_store, e)))?;
+        let rate_limiter = RateLimiter::new(config.rate_limit.clone()).with_store(state.clone());
+        let rate_limiter = match &config.rate_limit_file {
+            Some(path) => rate_limiter.watch(path),
+            None => rate_limiter,
+        };
+
+        let usage = match &config.usage_ledger {
+            Some(path) => UsageLedger::open(path).unwrap_or_else(|e| {
+                log::error!("Cannot open usage ledger {}: {}, keeping usage in memory", path.display(), e);
+                UsageLedger::in_memory()
+            }),
+            None => UsageLedger::in_memory(),
+        };
+
+        Ok(Self {
             session_id: Uuid::new_v4(),
             config: config.clone(),
-            http_client: Client::builder().timeout(config.timeout).build().unwrap(),
-            rate_limits: Arc::new(RwLock::new(HashMap::new())),
+            http_client,
+            clients,
+            proxy,
+            browser,
+            retry,
+            breakers: breaker::global(),
+            detector: detect::global(),
+            sessions: Arc::new(SessionPool::from_env().with_store(state.clone())),
+            limiter: limits::global(),
+            hedger: hedge::global(),
+            rate_limiter: Arc::new(rate_limiter),
+            budgets: Arc::new(Budgets::new(config.budget.clone()).with_store(state)),
+            usage: Arc::new(usage),
             start_time: Utc::now(),
+        })
+    }
+
+    /// Pick the client for a call: an explicit `profile` argument wins, then the
+    /// profile mapped to the caller's API key, then the instance default. A
+    /// key mapped to a profile is bound to that account, and a key with a
+    /// `profiles` scope may only pick the profiles it lists.
+    pub fn client_for(&self, profile: Option<&str>, key: Option<&ApiKey>) -> Result<&BrightDataClient, BrightDataError> {
+        let mapped = key.and_then(|key| self.config.profile_api_keys.get(&key.name));
+        let name = profile.or(mapped.map(String::as_str)).unwrap_or(&self.config.default_profile);
+
+        if let Some(key) = key {
+            match mapped {
+                Some(mapped) if mapped != name => {
+                    return Err(BrightDataError::Forbidden { key: key.name.clone(), scope: format!("profile {}", name) });
+                }
+                Some(_) => {}
+                None => key.check_profile(name)?,
+            }
         }
+        self.clients
+            .get(name)
+            .ok_or_else(|| BrightDataError::InvalidParams(format!("Unknown profile '{}'", name)))
     }
 }
 
@@ -77,6 +270,16 @@ pub struct McpError {
     pub data: Option<serde_json::Value>,
 }
 
+impl From<&BrightDataError> for McpError {
+    fn from(err: &BrightDataError) -> Self {
+        Self {
+            code: err.mcp_code(),
+            message: err.to_string(),
+            data: Some(err.mcp_data()),
+        }
+    }
+}
+
 pub struct BrightDataUrls;
 
 impl BrightDataUrls {
@@ -84,70 +287,176 @@ impl BrightDataUrls {
 }
 
 pub async fn handle_mcp_request(
-    _req: HttpRequest,
+    req: HttpRequest,
     payload: web::Json<McpRequest>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
+    let api_key = request_api_key(&req);
+    let key = match authenticate(&state.config, api_key.as_deref()) {
+        Ok(key) => key,
+        Err(err) => {
+            let caller = client_identity(&req, &state.config.trusted_proxies);
+            log::warn!("Rejected /mcp request from {}: {}", caller, err);
+            return Ok(HttpResponse::Unauthorized()
+                .insert_header(("WWW-Authenticate", "Bearer"))
+                .json(McpResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: payload.id.clone(),
+                    result: None,
+                    error: Some(McpError::from(&err)),
+                }));
+        }
+    };
+    let client_id = match key {
+        Some(key) => key.identity(),
+        None => client_identity(&req, &state.config.trusted_proxies),
+    };
     let req = payload.into_inner();
     let id = req.id.clone();
 
+    // Tightest rate limit of a tool call, reported in `RateLimit-*` headers.
+    let mut rate_status: Option<RateLimitStatus> = None;
+    // Status of a failed tool call that is not reported as HTTP 200: a scope
+    // the API key does not have, or a zone over its rate limit.
+    let mut failed_status: Option<HttpResponseBuilder> = None;
+
     // Match returns Result<McpResponse, String>
     let mcp_result: Result<McpResponse, String> = match req.method.as_str() {
-        "tools/list" => Ok(McpResponse {
-            jsonrpc: "2.0".to_string(),
-            id,
-            result: Some(serde_json::json!({
-                "tools": [
-                    { "name": "scrape_website", "description": "Scrape a web page" },
-                    { "name": "search_web", "description": "Perform a web search" },
-                    { "name": "extract_data", "description": "Extract structured data from a webpage (WIP)" }
-                ]
-            })),
-            error: None,
-        }),
+        "tools/list" => {
+            let tools: Vec<serde_json::Value> = [
+                ("scrape_website", "Scrape a web page"),
+                ("search_web", "Perform a web search"),
+                ("extract_data", "Extract structured data from a webpage (WIP)"),
+            ]
+            .into_iter()
+            .filter(|(name, _)| key.is_none_or(|key| key.scopes.allows_tool(name)))
+            .map(|(name, description)| serde_json::json!({ "name": name, "description": description }))
+            .collect();
+            Ok(McpResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: Some(serde_json::json!({ "tools": tools })),
+                error: None,
+            })
+        }
 
         "tools/call" => {
             if let Some(params) = req.params {
                 let name = params.get("name").and_then(|v| v.as_str()).unwrap_or("");
                 let args = params.get("arguments").cloned().unwrap_or_default();
 
-                if !check_rate_limit(name, &state).await {
-                    return Ok(HttpResponse::TooManyRequests().json(McpResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id,
-                        result: None,
-                        error: Some(McpError {
-                            code: -32000,
-                            message: "Rate limit exceeded".to_string(),
-                            data: None,
-                        }),
-                    }));
+                if let Some(key) = key {
+                    let url = args.get("url").and_then(|v| v.as_str());
+                    if let Err(err) = key.check_tool(name).and_then(|_| url.map_or(Ok(()), |url| key.check_url(url))) {
+                        log::warn!("Refused {} calling {}: {}", client_id, name, err);
+                        return Ok(HttpResponse::Forbidden().json(McpResponse {
+                            jsonrpc: "2.0".to_string(),
+                            id,
+                            result: None,
+                            error: Some(McpError::from(&err)),
+                        }));
+                    }
+                }
+
+                let call_deadline = deadline::from_args(&args, state.config.timeout, state.config.max_call_timeout);
+                let max_wait = match ratelimit::requested_wait(&args) {
+                    Some(wait) => wait.min(state.config.rate_limit_max_wait),
+                    None if state.config.rate_limit_queue => state.config.rate_limit_max_wait,
+                    None => Duration::ZERO,
                 }
+                .min(deadline::remaining(call_deadline));
+
+                let queued_at = Instant::now();
+                match state.rate_limiter.wait_call(name, &client_id, max_wait).await {
+                    Ok(status) => rate_status = status,
+                    Err(err) => {
+                        log::warn!("Rate limited {} calling {}: {}", client_id, name, err);
+                        return Ok(too_many_requests(&err).json(McpResponse {
+                            jsonrpc: "2.0".to_string(),
+                            id,
+                            result: None,
+                            error: Some(McpError::from(&err)),
+                        }));
+                    }
+                }
+
+                let profile = args.get("profile").and_then(|v| v.as_str());
+                let client = match state.client_for(profile, key) {
+                    Ok(client) => client,
+                    Err(err) => {
+                        let mut res = match err {
+                            BrightDataError::Forbidden { .. } => {
+                                log::warn!("Refused {} calling {}: {}", client_id, name, err);
+                                HttpResponse::Forbidden()
+                            }
+                            _ => HttpResponse::Ok(),
+                        };
+                        return Ok(res.json(McpResponse {
+                            jsonrpc: "2.0".to_string(),
+                            id,
+                            result: None,
+                            error: Some(McpError::from(&err)),
+                        }));
+                    }
+                };
 
+                let rate_limit_wait = queued_at.elapsed();
+                let ctx = CallContext {
+                    profile: client.config(),
+                    client: &client_id,
+                    api_key: key,
+                    deadline: call_deadline,
+                    sticky: StickyRequest::from_args(&args).map(|req| req.owned_by(&client_id)),
+                    hedge: name == "scrape_website" && state.hedger.config().enabled_for(&args),
+                    rate_limit_wait_until: queued_at + max_wait,
+                    attempts: Arc::default(),
+                    cost: Mutex::new(0.0),
+                };
+
+                let started = Instant::now();
                 let result = match name {
-                    "scrape_website" => handle_scrape_website(&args, &state).await,
-                    "search_web" => handle_search_web(&args, &state).await,
+                    "scrape_website" => handle_scrape_website(&args, &ctx, &state).await,
+                    "search_web" => handle_search_web(&args, &ctx, &state).await,
                     "extract_data" => handle_extract_placeholder(&args).await,
-                    _ => Err("Unknown tool".to_string()),
+                    _ => Err(BrightDataError::InvalidParams(format!("Unknown tool: {}", name))),
                 };
+                if let Some(sticky) = &ctx.sticky {
+                    state.sessions.finish(sticky).await;
+                }
+                state.usage.record(usage_record(&client_id, name, &result, &ctx, started));
 
                 Ok(match result {
-                    Ok(content) => McpResponse {
+                    Ok(output) => McpResponse {
                         jsonrpc: "2.0".to_string(),
                         id,
-                        result: Some(serde_json::json!({ "content": content })),
+                        result: Some(serde_json::json!({
+                            "content": output.content,
+                            "_meta": {
+                                "attempts": output.attempts,
+                                "zone": output.zone,
+                                "fallbacks": output.fallbacks,
+                                "hedged": output.hedged,
+                                "cost": output.cost,
+                                "budgetRemaining": output.budget_remaining,
+                                "rateLimitWaitMs": rate_limit_wait.as_millis() as u64,
+                            },
+                        })),
                         error: None,
                     },
-                    Err(msg) => McpResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id,
-                        result: None,
-                        error: Some(McpError {
-                            code: -32603,
-                            message: msg,
-                            data: None,
-                        }),
-                    },
+                    Err(err) => {
+                        log::warn!("Tool {} failed: {}", name, err);
+                        failed_status = match err {
+                            BrightDataError::Forbidden { .. } => Some(HttpResponse::Forbidden()),
+                            BrightDataError::RateLimitExceeded { .. } => Some(too_many_requests(&err)),
+                            _ => None,
+                        };
+                        McpResponse {
+                            jsonrpc: "2.0".to_string(),
+                            id,
+                            result: None,
+                            error: Some(McpError::from(&err)),
+                        }
+                    }
                 })
             } else {
                 Ok(McpResponse {
@@ -177,7 +486,21 @@ pub async fn handle_mcp_request(
 
     // Wrap the unified result into an HTTP response
     match mcp_result {
-        Ok(resp) => Ok(HttpResponse::Ok().json(resp)),
+        Ok(resp) => {
+            let mut res = match failed_status {
+                Some(res) => res,
+                None => {
+                    let mut res = HttpResponse::Ok();
+                    if let Some(status) = &rate_status {
+                        res.insert_header(("RateLimit-Limit", status.limit.to_string()))
+                            .insert_header(("RateLimit-Remaining", status.remaining.to_string()))
+                            .insert_header(("RateLimit-Reset", (status.reset.as_secs_f64().ceil() as u64).to_string()));
+                    }
+                    res
+                }
+            };
+            Ok(res.json(resp))
+        }
         Err(e) => Ok(HttpResponse::InternalServerError().json(McpResponse {
             jsonrpc: "2.0".to_string(),
             id: req.id,
@@ -191,114 +514,448 @@ pub async fn handle_mcp_request(
     }
 }
 
+/// HTTP 429 for a call or zone rate-limit rejection, telling the client when
+/// to retry.
+fn too_many_requests(err: &BrightDataError) -> HttpResponseBuilder {
+    let mut res = HttpResponse::TooManyRequests();
+    if let BrightDataError::RateLimitExceeded { limit, retry_after, .. } = err {
+        let wait = retry_after.as_secs_f64().ceil() as u64;
+        res.insert_header(("RateLimit-Limit", limit.to_string()))
+            .insert_header(("RateLimit-Remaining", "0"))
+            .insert_header(("RateLimit-Reset", wait.to_string()))
+            .insert_header(("Retry-After", wait.to_string()));
+    }
+    res
+}
+
 
 pub async fn health_check(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let circuits = state.breakers.snapshot();
+    let degraded = circuits.iter().any(|c| c.state != BreakerState::Closed);
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
+        "status": if degraded { "degraded" } else { "healthy" },
         "session_id": state.session_id,
         "uptime_seconds": (Utc::now() - state.start_time).num_seconds(),
+        "circuits": circuits,
+        "sticky_sessions": state.sessions.snapshot().await.len(),
+        "state_store": state.config.state_store.to_string(),
     })))
 }
 
-pub async fn cors_handler() -> HttpResponse {
-    HttpResponse::Ok()
-        .insert_header(("Access-Control-Allow-Origin", "*"))
-        .insert_header(("Access-Control-Allow-Methods", "POST, GET, OPTIONS"))
-        .insert_header(("Access-Control-Allow-Headers", "Content-Type, Authorization"))
-        .finish()
+/// Tool name an API key's `tools` scope must allow to read `/usage`.
+pub const USAGE_SCOPE: &str = "usage";
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub client: Option<String>,
+    pub tool: Option<String>,
+    /// `json` (default) for totals, `csv` for the matching records.
+    pub format: Option<String>,
+}
+
+/// Usage totals from the ledger, or the raw records as CSV.
+/// `/usage` names every caller, so it always needs an API key, one whose
+/// `tools` scope allows `usage`.
+pub async fn usage_report(req: HttpRequest, query: web::Query<UsageQuery>, state: web::Data<AppState>) -> HttpResponse {
+    let key = match authenticate(&state.config, request_api_key(&req).as_deref()) {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({ "error": "/usage needs API_KEYS to be configured" }))
+        }
+        Err(err) => {
+            return HttpResponse::Unauthorized()
+                .insert_header(("WWW-Authenticate", "Bearer"))
+                .json(serde_json::json!({ "error": err.to_string() }))
+        }
+    };
+    if let Err(err) = key.check_tool(USAGE_SCOPE) {
+        log::warn!("Refused {} reading /usage: {}", key.identity(), err);
+        return HttpResponse::Forbidden().json(serde_json::json!({ "error": err.to_string() }));
+    }
+
+    let query = query.into_inner();
+    let parse = |time: Option<&String>, end_of_day| time.map(|t| usage::parse_time(t, end_of_day)).transpose();
+    let (from, to) = match (parse(query.from.as_ref(), false), parse(query.to.as_ref(), true)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e })),
+    };
+    let filter = UsageFilter { from, to, client: query.client, tool: query.tool };
+
+    // A file ledger is read and parsed whole, so keep it off the workers.
+    let usage = state.usage.clone();
+    let read = web::block(move || usage.query(&filter)).await.map_err(std::io::Error::other);
+    let records = match read.and_then(|records| records) {
+        Ok(records) => records,
+        Err(e) => {
+            log::error!("Failed to read usage ledger: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "usage ledger unavailable" }));
+        }
+    };
+
+    if query.format.as_deref() == Some("csv") {
+        let mut csv = String::from(UsageRecord::CSV_HEADER);
+        csv.push('\n');
+        for record in &records {
+            csv.push_str(&record.to_csv());
+            csv.push('\n');
+        }
+        return HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header(("Content-Disposition", "attachment; filename=\"usage.csv\""))
+            .body(csv);
+    }
+
+    let (total, groups) = usage::summarize(&records);
+    HttpResponse::Ok().json(serde_json::json!({
+        "from": from,
+        "to": to,
+        "total": total,
+        "groups": groups,
+    }))
 }
 
-async fn check_rate_limit(tool: &str, state: &web::Data<AppState>) -> bool {
-    let mut limits = state.rate_limits.write().await;
-    let now = Utc::now();
-    let entry = limits.entry(tool.to_string()).or_insert((0, now));
+/// Prometheus text exposition of the server's runtime state.
+pub async fn metrics(state: web::Data<AppState>) -> HttpResponse {
+    let mut out = String::new();
 
-    let limit = 10;
-    let window = chrono::Duration::seconds(60);
+    out.push_str("# HELP brightdata_circuit_state Circuit breaker state (0 closed, 1 half-open, 2 open)\n");
+    out.push_str("# TYPE brightdata_circuit_state gauge\n");
+    let circuits = state.breakers.snapshot();
+    for c in &circuits {
+        out.push_str(&format!("brightdata_circuit_state{{key=\"{}\"}} {}\n", c.key, c.state.as_gauge()));
+    }
+    out.push_str("# HELP brightdata_circuit_consecutive_failures Consecutive upstream failures per circuit\n");
+    out.push_str("# TYPE brightdata_circuit_consecutive_failures gauge\n");
+    for c in &circuits {
+        out.push_str(&format!("brightdata_circuit_consecutive_failures{{key=\"{}\"}} {}\n", c.key, c.consecutive_failures));
+    }
 
-    if now - entry.1 > window {
-        entry.0 = 0;
-        entry.1 = now;
+    let queues = state.limiter.stats();
+    out.push_str("# HELP brightdata_queue_depth Requests waiting for a concurrency slot\n");
+    out.push_str("# TYPE brightdata_queue_depth gauge\n");
+    for q in &queues {
+        out.push_str(&format!("brightdata_queue_depth{{scope=\"{}\"}} {}\n", q.scope, q.waiting));
+    }
+    out.push_str("# HELP brightdata_in_flight Requests holding a concurrency slot\n");
+    out.push_str("# TYPE brightdata_in_flight gauge\n");
+    for q in &queues {
+        out.push_str(&format!("brightdata_in_flight{{scope=\"{}\"}} {}\n", q.scope, q.in_flight));
     }
 
-    if entry.0 >= limit {
-        false
-    } else {
-        entry.0 += 1;
-        true
+    out.push_str("# HELP brightdata_rate_limit_waiting Calls waiting for a rate-limit slot\n");
+    out.push_str("# TYPE brightdata_rate_limit_waiting gauge\n");
+    out.push_str(&format!("brightdata_rate_limit_waiting {}\n", state.rate_limiter.waiting()));
+    out.push_str("# HELP brightdata_rate_limit_keys Rate-limit buckets currently tracked\n");
+    out.push_str("# TYPE brightdata_rate_limit_keys gauge\n");
+    out.push_str(&format!("brightdata_rate_limit_keys {}\n", state.rate_limiter.tracked_keys().await));
+
+    out.push_str("# HELP brightdata_spend_usd Estimated spend today (UTC)\n");
+    out.push_str("# TYPE brightdata_spend_usd gauge\n");
+    // Per-client spend is left out to keep the series count bounded.
+    for spend in state.budgets.snapshot().await.iter().filter(|s| !s.scope.starts_with("client:")) {
+        out.push_str(&format!("brightdata_spend_usd{{scope=\"{}\"}} {}\n", spend.scope, spend.spent));
     }
+
+    let hedges = state.hedger.stats();
+    out.push_str("# HELP brightdata_hedges_total Hedge requests sent for slow scrapes\n");
+    out.push_str("# TYPE brightdata_hedges_total counter\n");
+    out.push_str(&format!("brightdata_hedges_total {}\n", hedges.hedges));
+    out.push_str("# HELP brightdata_hedge_wins_total Hedge requests that finished first\n");
+    out.push_str("# TYPE brightdata_hedge_wins_total counter\n");
+    out.push_str(&format!("brightdata_hedge_wins_total {}\n", hedges.hedge_wins));
+    out.push_str("# HELP brightdata_hedge_delay_seconds Current delay before a hedge is sent\n");
+    out.push_str("# TYPE brightdata_hedge_delay_seconds gauge\n");
+    out.push_str(&format!("brightdata_hedge_delay_seconds {}\n", hedges.delay_ms as f64 / 1000.0));
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out)
 }
 
+/// The configured key matching `api_key`. Without configured keys calls are
+/// only let through when the server was started with `ALLOW_UNAUTHENTICATED`.
+fn authenticate<'a>(config: &'a Config, api_key: Option<&str>) -> Result<Option<&'a ApiKey>, BrightDataError> {
+    let keys = &config.api_keys;
+    if keys.is_empty() {
+        return if config.allow_unauthenticated {
+            Ok(None)
+        } else {
+            Err(BrightDataError::Unauthorized("no API keys configured".to_string()))
+        };
+    }
+    match api_key.filter(|k| !k.is_empty()) {
+        None => Err(BrightDataError::Unauthorized("missing API key".to_string())),
+        Some(api_key) => keys
+            .authenticate(api_key)
+            .map(Some)
+            .ok_or_else(|| BrightDataError::Unauthorized("unknown API key".to_string())),
+    }
+}
 
+/// API key sent by the caller, either as `X-Api-Key` or as a bearer token.
+fn request_api_key(req: &HttpRequest) -> Option<String> {
+    let headers = req.headers();
+    if let Some(key) = headers.get("X-Api-Key").and_then(|v| v.to_str().ok()) {
+        return Some(key.to_string());
+    }
+    headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string())
+}
+
+fn usage_record(client: &str, tool: &str, result: &Result<ToolOutput, BrightDataError>, ctx: &CallContext<'_>, started: Instant) -> UsageRecord {
+    let mut record = UsageRecord {
+        timestamp: Utc::now(),
+        client: client.to_string(),
+        tool: tool.to_string(),
+        zone: None,
+        success: result.is_ok(),
+        error: None,
+        attempts: ctx.attempts.load(Ordering::Relaxed),
+        bytes: 0,
+        cost: ctx.cost(),
+        latency_ms: started.elapsed().as_millis() as u64,
+    };
+    match result {
+        Ok(output) => {
+            record.zone = output.zone.clone();
+            record.bytes = output.content.len() as u64;
+        }
+        Err(err) => {
+            record.zone = err.context().and_then(|c| c.zone.clone());
+            record.error = Some(err.kind().to_string());
+        }
+    }
+    record
+}
 
+/// Identity used to key the caller's rate limits.
+fn client_identity(req: &HttpRequest, trusted: &TrustedProxies) -> String {
+    let forwarded = req.headers().get("X-Forwarded-For").and_then(|v| v.to_str().ok());
+    let ip = req.peer_addr().map(|addr| trusted.client_ip(addr.ip(), forwarded));
+    identity::client_id(ip)
+}
 
+/// Per-call settings shared by everything a tool call does upstream.
+struct CallContext<'a> {
+    profile: &'a BrightDataConfig,
+    /// Caller identity used for rate limits and budgets.
+    client: &'a str,
+    /// Key the call was authenticated with, limiting the zones it may use.
+    api_key: Option<&'a ApiKey>,
+    deadline: Instant,
+    sticky: Option<StickyRequest>,
+    /// Race slow proxy fetches against a second session (see `hedge`).
+    hedge: bool,
+    /// Until when the call may wait for rate-limit slots, zones included.
+    rate_limit_wait_until: Instant,
+    /// Requests sent for the call so far, over every zone step and hedge.
+    attempts: Arc<AtomicU32>,
+    /// Estimated cost in USD charged to the budgets so far, failed steps and
+    /// hedges included.
+    cost: Mutex<f64>,
+}
 
+impl CallContext<'_> {
+    fn charge(&self, usd: f64) {
+        *self.cost.lock().unwrap_or_else(|e| e.into_inner()) += usd;
+    }
 
-async fn handle_scrape_website(args: &serde_json::Value, state: &web::Data<AppState>) -> Result<String, String> {
-    let url = args.get("url").and_then(|v| v.as_str()).ok_or("Missing 'url'")?;
+    fn cost(&self) -> f64 {
+        *self.cost.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Content produced by a tool handler plus bookkeeping reported in `_meta`.
+#[derive(Default)]
+struct ToolOutput {
+    content: String,
+    attempts: u32,
+    zone: Option<String>,
+    fallbacks: Vec<String>,
+    hedged: bool,
+    /// Estimated cost in USD of every billed request the call made.
+    cost: f64,
+    budget_remaining: Option<f64>,
+}
+
+async fn handle_scrape_website(args: &serde_json::Value, ctx: &CallContext<'_>, state: &web::Data<AppState>) -> Result<ToolOutput, BrightDataError> {
+    let url = args
+        .get("url")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| BrightDataError::InvalidParams("Missing 'url'".into()))?;
     let format = args.get("format").and_then(|v| v.as_str()).unwrap_or("markdown");
 
-    let mut payload = serde_json::json!({
-        "url": url,
-        "zone": state.config.web_unlocker_zone,
-        "format": "raw",
-    });
+    let payload = request::scrape_payload(ctx.profile, url, format == "markdown");
+    run_chain("scrape_website", ZoneStep::Unlocker, &payload, ctx, state).await
+}
+
+async fn handle_search_web(args: &serde_json::Value, ctx: &CallContext<'_>, state: &web::Data<AppState>) -> Result<ToolOutput, BrightDataError> {
+    let query = args
+        .get("query")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| BrightDataError::InvalidParams("Missing 'query'".into()))?;
+    let search = SearchRequest {
+        query: query.to_string(),
+        engine: args.get("engine").and_then(|v| v.as_str()).map(String::from),
+        cursor: args.get("cursor").and_then(|v| v.as_str()).map(String::from),
+    };
+
+    let payload = request::search_payload(ctx.profile, &search);
+    run_chain("search_web", ZoneStep::Serp, &payload, ctx, state).await
+}
+
+/// Run `payload` through the tool's zone fallback chain, moving to the next
+/// zone when a step is blocked or only returns a JavaScript shell.
+async fn run_chain(tool: &str, default: ZoneStep, payload: &serde_json::Value, ctx: &CallContext<'_>, state: &web::Data<AppState>) -> Result<ToolOutput, BrightDataError> {
+    let chain = state.config.fallback.chain(tool, default);
+    let mut tried = Vec::new();
+
+    for (i, step) in chain.iter().enumerate() {
+        if deadline::is_expired(ctx.deadline) {
+            return Err(deadline::exceeded(tool));
+        }
+
+        let zone = step.zone(ctx.profile).to_string();
+        if let Some(key) = ctx.api_key {
+            // The payload's URL, not the tool arguments: searches build theirs.
+            key.check_zone(&zone)?;
+            key.check_url(payload["url"].as_str().unwrap_or_default())?;
+        }
+        let max_wait = ctx.rate_limit_wait_until.saturating_duration_since(Instant::now());
+        state.rate_limiter.wait_zone(&zone, max_wait).await?;
+        let reservation = state.budgets.reserve(ctx.client, tool, &zone).await?;
+        let is_last = i + 1 == chain.len();
+
+        let result = match step {
+            ZoneStep::Proxy(_) => proxy_request(tool, payload, &zone, ctx, state).await,
+            ZoneStep::Browser => browser_request(payload, &zone, ctx, state).await,
+            _ => {
+                let mut payload = payload.clone();
+                payload["zone"] = serde_json::json!(zone);
+                post_request(tool, &payload, ctx, state).await
+            }
+        };
 
-    if format == "markdown" {
-        payload["data_format"] = serde_json::json!("markdown");
+        let bytes = result.as_ref().ok().map(|output| output.content.len() as u64);
+        ctx.charge(state.budgets.settle(reservation, bytes).await);
+
+        match result {
+            Ok(output) if !is_last && fallback::needs_js(&output.content) => {
+                log::info!("{}: zone {} returned a JavaScript-only page, falling back", tool, zone);
+            }
+            Ok(mut output) => {
+                // Counted per request, so failed steps and hedges are included.
+                output.attempts = ctx.attempts.load(Ordering::Relaxed);
+                output.cost = ctx.cost();
+                output.budget_remaining = state.budgets.remaining(ctx.client, tool, &zone).await;
+                output.zone = Some(zone);
+                output.fallbacks = tried;
+                return Ok(output);
+            }
+            Err(err) if !is_last && fallback::should_fall_back(&err) => {
+                log::info!("{}: zone {} failed ({}), falling back", tool, zone, err);
+            }
+            Err(err) => return Err(err),
+        }
+        tried.push(step.to_string());
     }
 
-    let res = state.http_client
-        .post(BrightDataUrls::REQUEST_API)
-        .header("Authorization", format!("Bearer {}", state.config.api_token))
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
+    Err(BrightDataError::ToolError(format!("Empty zone fallback chain for {}", tool)))
+}
 
-    let body = res.text().await.map_err(|e| e.to_string())?;
-    Ok(body)
+/// Fetch the payload's target URL directly through a proxy-mode zone,
+/// switching to a fresh session when the page looks blocked.
+async fn proxy_request(label: &str, payload: &serde_json::Value, zone: &str, ctx: &CallContext<'_>, state: &web::Data<AppState>) -> Result<ToolOutput, BrightDataError> {
+    let url = payload["url"].as_str().unwrap_or_default();
+    let proxy = &state.proxy;
+    let retry = state.retry.with_deadline(ctx.deadline).counting(ctx.attempts.clone());
+    let mut target = ProxyTarget::zone(zone);
+    // Other calls go without a session and share one pooled client; only
+    // rotations and hedges need a fresh one.
+    target.session = match &ctx.sticky {
+        Some(req) => Some(state.sessions.session_for(req).await),
+        None => None,
+    };
+    let unblock = Unblock {
+        hedger: &state.hedger,
+        // Hedges run on a fresh session, so sticky calls are never hedged.
+        hedge: ctx.hedge && ctx.sticky.is_none(),
+        detector: &state.detector,
+        rotate: true,
+        sticky: ctx.sticky.as_ref().map(|req| (&*state.sessions, req)),
+    };
+    let page = proxy
+        .fetch_unblocked(target, url, &retry, label, unblock, || admit_hedge(label, zone, ctx, state))
+        .await?;
+    Ok(ToolOutput { content: page.body, attempts: page.attempts, hedged: page.hedged, ..Default::default() })
 }
 
-async fn handle_search_web(args: &serde_json::Value, state: &web::Data<AppState>) -> Result<String, String> {
-    let query = args.get("query").and_then(|v| v.as_str()).ok_or("Missing 'query'")?;
-    let engine = args.get("engine").and_then(|v| v.as_str()).unwrap_or("google");
-    let cursor = args.get("cursor").and_then(|v| v.as_str()).unwrap_or("0");
-
-    let search_url = build_search_url(engine, query, cursor);
-
-    let payload = serde_json::json!({
-        "url": search_url,
-        "zone": state.config.web_unlocker_zone,
-        "format": "raw",
-        "data_format": "markdown"
-    });
-
-    let res = state.http_client
-        .post(BrightDataUrls::REQUEST_API)
-        .header("Authorization", format!("Bearer {}", state.config.api_token))
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let body = res.text().await.map_err(|e| e.to_string())?;
-    Ok(body)
+/// Render the payload's target URL in a Scraping Browser zone. Not hedged:
+/// every tab is a billed browser session.
+async fn browser_request(payload: &serde_json::Value, zone: &str, ctx: &CallContext<'_>, state: &web::Data<AppState>) -> Result<ToolOutput, BrightDataError> {
+    let url = payload["url"].as_str().unwrap_or_default();
+    ctx.attempts.fetch_add(1, Ordering::Relaxed);
+    let content = state.browser.fetch(zone, url, Some(ctx.deadline)).await?;
+    state.detector.check(url, &content, Some(zone))?;
+    Ok(ToolOutput { content, attempts: 1, ..Default::default() })
 }
 
-async fn handle_extract_placeholder(_args: &serde_json::Value) -> Result<String, String> {
-    Ok("🧠 Extract tool placeholder: AI-based structured data extraction coming soon.".to_string())
+/// Send `payload` to the Bright Data request API, hedged like proxy fetches
+/// when the call asked for it.
+async fn post_request(label: &str, payload: &serde_json::Value, ctx: &CallContext<'_>, state: &web::Data<AppState>) -> Result<ToolOutput, BrightDataError> {
+    let profile = ctx.profile;
+    let zone = payload["zone"].as_str().unwrap_or_default();
+    let url = payload["url"].as_str().unwrap_or_default();
+    let key = breaker::key(zone, &profile.endpoint);
+    let retry = state.retry.with_deadline(ctx.deadline).counting(ctx.attempts.clone());
+
+    let res = state
+        .hedger
+        .run(ctx.hedge, || admit_hedge(label, zone, ctx, state), |_| {
+            let (key, retry) = (&key, &retry);
+            async move {
+                let _permit = state.limiter.acquire(zone, url, Some(ctx.deadline)).await?;
+                let res = state
+                    .breakers
+                    .call(key, retry::send(retry, label, || {
+                        state.http_client
+                            .post(&profile.endpoint)
+                            .header("Authorization", request::authorization(profile))
+                            .json(payload)
+                    }))
+                    .await?;
+
+                let attempts = res.attempts;
+                let status = res.value.status();
+                let headers = res.value.headers().clone();
+                let body = request::check_response(status, &headers, res.value.text().await?, payload)?;
+                state.detector.check(url, &body, Some(zone))?;
+                Ok((body, attempts))
+            }
+        })
+        .await?;
+    let (content, attempts) = res.value;
+    Ok(ToolOutput { content, attempts, hedged: res.hedged, ..Default::default() })
 }
 
-fn build_search_url(engine: &str, query: &str, cursor: &str) -> String {
-    let q = urlencoding::encode(query);
-    let page: usize = cursor.parse().unwrap_or(0);
-    let start = page * 10;
+/// Count a hedge against the zone's rate limit and the call's budgets before
+/// it is sent; its reserved estimate is what it is charged. A hedge that does
+/// not fit is not sent and the call waits for its first request.
+async fn admit_hedge(tool: &str, zone: &str, ctx: &CallContext<'_>, state: &web::Data<AppState>) -> Result<(), BrightDataError> {
+    state.rate_limiter.check_zone(zone).await?;
+    ctx.charge(state.budgets.reserve(ctx.client, tool, zone).await?.keep());
+    Ok(())
+}
 
-    match engine {
-        "yandex" => format!("https://yandex.com/search/?text={q}&p={page}"),
-        "bing" => format!("https://www.bing.com/search?q={q}&first={}", start + 1),
-        "duckduckgo" => format!("https://duckduckgo.com/?q={q}&s={start}"),
-        _ => format!("https://www.google.com/search?q={q}&start={start}"),
-    }
+async fn handle_extract_placeholder(_args: &serde_json::Value) -> Result<ToolOutput, BrightDataError> {
+    Ok(ToolOutput {
+        content: "🧠 Extract tool placeholder: AI-based structured data extraction coming soon.".to_string(),
+        ..Default::default()
+    })
 }
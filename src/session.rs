@@ -0,0 +1,192 @@
+// src/session.rs
+//! Named sticky proxy sessions.
+//!
+//! Bright Data keeps the same exit IP for as long as a `-session-<id>` stays
+//! in use. The pool maps caller-chosen names (`"checkout-flow"`) to session
+//! ids with a sliding TTL so multi-step flows can reuse one IP across tool
+//! calls, and rotate or release it explicitly.
+//!
+//! Tool arguments: `sticky_session` (name), `session_ttl_secs` and
+//! `session_action` (`"rotate"` before the request, `"release"` after it).
+//! The server scopes these names to the caller with
+//! [`StickyRequest::owned_by`].
+//!
+//! Sessions are kept in the [`StateStore`], so servers sharing a store hand
+//! out the same session for a name.
+
+use crate::store::{self, MemoryStore, StateStore};
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+static GLOBAL: OnceLock<Arc<SessionPool>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionAction {
+    Rotate,
+    Release,
+}
+
+/// Sticky-session options of one tool call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StickyRequest {
+    pub name: String,
+    pub ttl: Option<Duration>,
+    pub action: Option<SessionAction>,
+}
+
+impl StickyRequest {
+    pub fn from_args(args: &Value) -> Option<Self> {
+        let name = args.get("sticky_session").and_then(|v| v.as_str())?;
+        Some(Self {
+            name: name.to_string(),
+            ttl: args.get("session_ttl_secs").and_then(|v| v.as_u64()).map(Duration::from_secs),
+            action: match args.get("session_action").and_then(|v| v.as_str()) {
+                Some("rotate") => Some(SessionAction::Rotate),
+                Some("release") => Some(SessionAction::Release),
+                _ => None,
+            },
+        })
+    }
+
+    /// Scope the name to one caller, so callers sharing the pool cannot
+    /// reuse, rotate or release each other's sessions. The space cannot occur
+    /// in a caller id (see `identity`), so different owners never collide.
+    pub fn owned_by(mut self, owner: &str) -> Self {
+        self.name = format!("{} {}", owner, self.name);
+        self
+    }
+}
+
+/// Store key prefix of sticky sessions.
+const KEY_PREFIX: &str = "session:";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub name: String,
+    pub id: String,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Debug)]
+pub struct SessionPool {
+    default_ttl: Duration,
+    max_ttl: Duration,
+    store: Arc<dyn StateStore>,
+}
+
+impl SessionPool {
+    pub fn new(default_ttl: Duration, max_ttl: Duration) -> Self {
+        Self {
+            default_ttl,
+            max_ttl,
+            store: Arc::new(MemoryStore::new()),
+        }
+    }
+
+    /// Keep sessions in `store` instead of this process.
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    pub fn from_env() -> Self {
+        let secs = |key: &str, default: u64| {
+            Duration::from_secs(env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default))
+        };
+        Self::new(secs("SESSION_TTL_SECS", 300), secs("SESSION_MAX_TTL_SECS", 3600))
+    }
+
+    fn ttl(&self, ttl: Option<Duration>) -> Duration {
+        ttl.unwrap_or(self.default_ttl).min(self.max_ttl)
+    }
+
+    /// The session id for `name`, creating one if it does not exist or has
+    /// expired. Every use extends the session by its TTL.
+    pub async fn acquire(&self, name: &str, ttl: Option<Duration>) -> String {
+        let ttl = self.ttl(ttl);
+        let key = format!("{}{}", KEY_PREFIX, name);
+        let stored = store::blocking(&self.store, move |store| {
+            let mut id = String::new();
+            store.update(&key, &mut |current| {
+                id = current.map(str::to_string).unwrap_or_else(new_session_id);
+                Some((id.clone(), Some(ttl)))
+            })?;
+            Ok(id)
+        })
+        .await;
+        match stored {
+            Ok(id) if !id.is_empty() => id,
+            Ok(_) => new_session_id(),
+            Err(e) => {
+                log::warn!("Failed to store sticky session '{}': {}", name, e);
+                new_session_id()
+            }
+        }
+    }
+
+    /// Give `name` a fresh session id (and so a new exit IP).
+    pub async fn rotate(&self, name: &str, ttl: Option<Duration>) -> String {
+        let id = new_session_id();
+        let (key, value, ttl) = (format!("{}{}", KEY_PREFIX, name), id.clone(), self.ttl(ttl));
+        if let Err(e) = store::blocking(&self.store, move |store| store.set(&key, &value, Some(ttl))).await {
+            log::warn!("Failed to store sticky session '{}': {}", name, e);
+        }
+        id
+    }
+
+    pub async fn release(&self, name: &str) -> bool {
+        let key = format!("{}{}", KEY_PREFIX, name);
+        store::blocking(&self.store, move |store| store.delete(&key)).await.unwrap_or_else(|e| {
+            log::warn!("Failed to release sticky session '{}': {}", name, e);
+            false
+        })
+    }
+
+    /// Session id to use for a call, applying a requested rotation first.
+    pub async fn session_for(&self, req: &StickyRequest) -> String {
+        match req.action {
+            Some(SessionAction::Rotate) => self.rotate(&req.name, req.ttl).await,
+            _ => self.acquire(&req.name, req.ttl).await,
+        }
+    }
+
+    /// Apply a requested release once the call is done.
+    pub async fn finish(&self, req: &StickyRequest) {
+        if req.action == Some(SessionAction::Release) {
+            self.release(&req.name).await;
+        }
+    }
+
+    pub async fn snapshot(&self) -> Vec<SessionInfo> {
+        let entries = store::blocking(&self.store, |store| store.scan(KEY_PREFIX)).await.unwrap_or_else(|e| {
+            log::warn!("Failed to read sticky sessions: {}", e);
+            Vec::new()
+        });
+        let mut sessions: Vec<SessionInfo> = entries
+            .into_iter()
+            .filter_map(|entry| {
+                Some(SessionInfo {
+                    name: entry.key.strip_prefix(KEY_PREFIX)?.to_string(),
+                    id: entry.value,
+                    expires_in_secs: entry.ttl.unwrap_or_default().as_secs(),
+                })
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.name.cmp(&b.name));
+        sessions
+    }
+}
+
+pub fn new_session_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Pool used by the tools; the server keeps its own in its configured store.
+pub fn global() -> Arc<SessionPool> {
+    GLOBAL
+        .get_or_init(|| Arc::new(SessionPool::from_env().with_store(store::global())))
+        .clone()
+}
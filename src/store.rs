@@ -0,0 +1,538 @@
+// src/store.rs
+//! Shared state for rate-limit counters, budgets, sticky sessions and cached
+//! entries.
+//!
+//! `STATE_STORE=memory` (the default) keeps state in the process. With
+//! `STATE_STORE=sqlite:/var/lib/snm/state.db` (needs the `sqlite` feature)
+//! every `snm_server` on the host shares one database, so replicas enforce
+//! one set of limits and budgets instead of one each.
+
+use crate::error::BrightDataError;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static GLOBAL: OnceLock<Arc<dyn StateStore>> = OnceLock::new();
+
+const SHARDS: usize = 16;
+/// Shard size at which expired entries are first swept out.
+const MIN_SWEEP: usize = 256;
+
+/// A live entry returned by [`StateStore::scan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    /// Time left before the entry expires, `None` if it never does.
+    pub ttl: Option<Duration>,
+}
+
+/// The new value and TTL for an entry, as returned to [`StateStore::update`].
+pub type Update = Option<(String, Option<Duration>)>;
+
+/// One [`Update`] per key, as returned to [`StateStore::update_many`];
+/// `None` leaves every key as it is.
+pub type Updates = Option<Vec<Update>>;
+
+/// Key-value store with per-entry expiry. Expired entries read as missing.
+pub trait StateStore: Send + Sync + fmt::Debug {
+    fn get(&self, key: &str) -> Result<Option<String>, BrightDataError>;
+
+    fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), BrightDataError>;
+
+    /// Remove `key`; returns whether a live entry was removed.
+    fn delete(&self, key: &str) -> Result<bool, BrightDataError>;
+
+    /// Read and replace `key` atomically, also against other processes using
+    /// the same store. `update` gets the current value and returns the new
+    /// value and TTL, or `None` to leave the entry as it is. It may be called
+    /// more than once.
+    fn update(&self, key: &str, update: &mut dyn FnMut(Option<&str>) -> Update) -> Result<(), BrightDataError>;
+
+    /// [`update`](Self::update) for several keys in one transaction. `update`
+    /// gets the current values in the order of `keys` and returns one update
+    /// per key, or `None` to leave all of them as they are.
+    fn update_many(
+        &self,
+        keys: &[String],
+        update: &mut dyn FnMut(&[Option<String>]) -> Updates,
+    ) -> Result<(), BrightDataError>;
+
+    /// Live entries whose key starts with `prefix`.
+    fn scan(&self, prefix: &str) -> Result<Vec<Entry>, BrightDataError>;
+
+    /// Whether other processes see this store's state. State that is not
+    /// shared can be kept in faster in-process structures instead.
+    fn is_shared(&self) -> bool {
+        true
+    }
+}
+
+/// Run `f` against `store` on the blocking thread pool, as a shared store may
+/// wait on disk or on other processes. Stores local to this process are
+/// called in place.
+pub async fn blocking<T, F>(store: &Arc<dyn StateStore>, f: F) -> Result<T, BrightDataError>
+where
+    F: FnOnce(&dyn StateStore) -> Result<T, BrightDataError> + Send + 'static,
+    T: Send + 'static,
+{
+    if !store.is_shared() {
+        return f(store.as_ref());
+    }
+    let store = store.clone();
+    tokio::task::spawn_blocking(move || f(store.as_ref()))
+        .await
+        .map_err(|e| BrightDataError::Unexpected(anyhow::anyhow!("State store task failed: {}", e)))?
+}
+
+/// Which store to use, from `STATE_STORE`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StoreConfig {
+    #[default]
+    Memory,
+    Sqlite(PathBuf),
+}
+
+impl StoreConfig {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.trim() {
+            "" | "memory" => Ok(StoreConfig::Memory),
+            spec => match spec.strip_prefix("sqlite:") {
+                Some(_) if !cfg!(feature = "sqlite") => {
+                    Err("STATE_STORE=sqlite needs snm-brightdata-client built with the `sqlite` feature".to_string())
+                }
+                Some(path) if !path.is_empty() => Ok(StoreConfig::Sqlite(PathBuf::from(path))),
+                _ => Err(format!("Invalid STATE_STORE '{}', expected memory or sqlite:<path>", spec)),
+            },
+        }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(&std::env::var("STATE_STORE").unwrap_or_default())
+    }
+
+    pub fn open(&self) -> Result<Arc<dyn StateStore>, BrightDataError> {
+        match self {
+            StoreConfig::Memory => Ok(Arc::new(MemoryStore::new())),
+            #[cfg(feature = "sqlite")]
+            StoreConfig::Sqlite(path) => Ok(Arc::new(SqliteStore::open(path)?)),
+            #[cfg(not(feature = "sqlite"))]
+            StoreConfig::Sqlite(_) => Err(BrightDataError::Unexpected(anyhow::anyhow!(
+                "STATE_STORE=sqlite needs snm-brightdata-client built with the `sqlite` feature"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for StoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreConfig::Memory => write!(f, "memory"),
+            StoreConfig::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch; expiry times are wall-clock so that
+/// processes sharing a store agree on them.
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Expiry of an entry written at `now` (truncated to whole milliseconds).
+/// Rounds up, plus the millisecond `now` may have lost, so an entry never
+/// expires before its TTL has passed, however short the TTL.
+fn expiry(ttl: Option<Duration>, now: u64) -> Option<u64> {
+    ttl.map(|ttl| {
+        let millis = ttl.as_nanos().div_ceil(1_000_000).min(u64::MAX as u128) as u64;
+        now.saturating_add(millis).saturating_add(1)
+    })
+}
+
+#[derive(Debug)]
+struct MemEntry {
+    value: String,
+    expires_at: Option<u64>,
+}
+
+impl MemEntry {
+    fn live(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+#[derive(Debug)]
+struct Shard {
+    entries: HashMap<String, MemEntry>,
+    sweep_at: usize,
+}
+
+impl Shard {
+    /// Drop expired entries once the shard has grown past its sweep mark.
+    fn sweep(&mut self, now: u64) {
+        if self.entries.len() >= self.sweep_at {
+            self.entries.retain(|_, e| e.live(now));
+            self.sweep_at = (self.entries.len() * 2).max(MIN_SWEEP);
+        }
+    }
+}
+
+/// Store local to this process.
+#[derive(Debug)]
+pub struct MemoryStore {
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(Shard { entries: HashMap::new(), sweep_at: MIN_SWEEP }))
+                .collect(),
+        }
+    }
+
+    fn index(key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % SHARDS
+    }
+
+    fn shard(&self, key: &str) -> std::sync::MutexGuard<'_, Shard> {
+        self.shards[Self::index(key)].lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<String>, BrightDataError> {
+        let now = now_ms();
+        Ok(self.shard(key).entries.get(key).filter(|e| e.live(now)).map(|e| e.value.clone()))
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), BrightDataError> {
+        let now = now_ms();
+        let mut shard = self.shard(key);
+        shard.sweep(now);
+        shard.entries.insert(key.to_string(), MemEntry { value: value.to_string(), expires_at: expiry(ttl, now) });
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, BrightDataError> {
+        let now = now_ms();
+        Ok(self.shard(key).entries.remove(key).is_some_and(|e| e.live(now)))
+    }
+
+    fn update(&self, key: &str, update: &mut dyn FnMut(Option<&str>) -> Update) -> Result<(), BrightDataError> {
+        let now = now_ms();
+        let mut shard = self.shard(key);
+        let current = shard.entries.get(key).filter(|e| e.live(now)).map(|e| e.value.as_str());
+        if let Some((value, ttl)) = update(current) {
+            shard.sweep(now);
+            shard.entries.insert(key.to_string(), MemEntry { value, expires_at: expiry(ttl, now) });
+        }
+        Ok(())
+    }
+
+    fn update_many(
+        &self,
+        keys: &[String],
+        update: &mut dyn FnMut(&[Option<String>]) -> Updates,
+    ) -> Result<(), BrightDataError> {
+        let now = now_ms();
+        // Shards are locked in index order so concurrent calls cannot deadlock.
+        let mut indexes: Vec<usize> = keys.iter().map(|key| Self::index(key)).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        let mut shards: HashMap<usize, _> = indexes
+            .into_iter()
+            .map(|i| (i, self.shards[i].lock().unwrap_or_else(|e| e.into_inner())))
+            .collect();
+
+        let current: Vec<Option<String>> = keys
+            .iter()
+            .map(|key| {
+                let shard = &shards[&Self::index(key)];
+                shard.entries.get(key).filter(|e| e.live(now)).map(|e| e.value.clone())
+            })
+            .collect();
+        for (key, change) in keys.iter().zip(update(&current).unwrap_or_default()) {
+            if let Some((value, ttl)) = change {
+                let shard = shards.get_mut(&Self::index(key)).expect("shard locked above");
+                shard.sweep(now);
+                shard.entries.insert(key.clone(), MemEntry { value, expires_at: expiry(ttl, now) });
+            }
+        }
+        Ok(())
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<Entry>, BrightDataError> {
+        let now = now_ms();
+        let mut entries = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            entries.extend(shard.entries.iter().filter(|(k, e)| k.starts_with(prefix) && e.live(now)).map(|(key, e)| {
+                Entry {
+                    key: key.clone(),
+                    value: e.value.clone(),
+                    ttl: e.expires_at.map(|at| Duration::from_millis(at - now)),
+                }
+            }));
+        }
+        Ok(entries)
+    }
+
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{expiry, now_ms, Entry, StateStore, Update, Updates};
+    use crate::error::BrightDataError;
+    use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// How long a writer waits for another process's transaction.
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Expired rows are deleted every this many writes.
+    const PURGE_EVERY: u64 = 1000;
+
+    fn db_error(e: rusqlite::Error) -> BrightDataError {
+        BrightDataError::Unexpected(anyhow::anyhow!("State store: {}", e))
+    }
+
+    /// Store in a SQLite database that several processes on one host can
+    /// open at once. Updates run in `BEGIN IMMEDIATE` transactions, which
+    /// SQLite serializes across processes.
+    #[derive(Debug)]
+    pub struct SqliteStore {
+        conn: Mutex<Connection>,
+        writes: AtomicU64,
+    }
+
+    impl SqliteStore {
+        pub fn open(path: &Path) -> Result<Self, BrightDataError> {
+            let conn = Connection::open(path).map_err(db_error)?;
+            conn.busy_timeout(BUSY_TIMEOUT).map_err(db_error)?;
+            conn.pragma_update(None, "journal_mode", "WAL").map_err(db_error)?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS state (
+                    key TEXT PRIMARY KEY,
+                    value TEXT NOT NULL,
+                    expires_at INTEGER
+                )",
+            )
+            .map_err(db_error)?;
+            Ok(Self { conn: Mutex::new(conn), writes: AtomicU64::new(0) })
+        }
+
+        fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+            self.conn.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        fn wrote(&self, conn: &Connection, now: u64) -> Result<(), BrightDataError> {
+            if self.writes.fetch_add(1, Ordering::Relaxed) % PURGE_EVERY == PURGE_EVERY - 1 {
+                conn.execute("DELETE FROM state WHERE expires_at <= ?1", params![now as i64]).map_err(db_error)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn read(conn: &Connection, key: &str, now: u64) -> rusqlite::Result<Option<String>> {
+        conn.query_row(
+            "SELECT value FROM state WHERE key = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+            params![key, now as i64],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    fn write(conn: &Connection, key: &str, value: &str, ttl: Option<Duration>, now: u64) -> rusqlite::Result<usize> {
+        conn.execute(
+            "INSERT INTO state (key, value, expires_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value, expires_at = excluded.expires_at",
+            params![key, value, expiry(ttl, now).map(|at| at as i64)],
+        )
+    }
+
+    impl StateStore for SqliteStore {
+        fn get(&self, key: &str) -> Result<Option<String>, BrightDataError> {
+            read(&self.conn(), key, now_ms()).map_err(db_error)
+        }
+
+        fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), BrightDataError> {
+            let now = now_ms();
+            let conn = self.conn();
+            write(&conn, key, value, ttl, now).map_err(db_error)?;
+            self.wrote(&conn, now)
+        }
+
+        fn delete(&self, key: &str) -> Result<bool, BrightDataError> {
+            let removed = self
+                .conn()
+                .execute(
+                    "DELETE FROM state WHERE key = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+                    params![key, now_ms() as i64],
+                )
+                .map_err(db_error)?;
+            Ok(removed > 0)
+        }
+
+        fn update(&self, key: &str, update: &mut dyn FnMut(Option<&str>) -> Update) -> Result<(), BrightDataError> {
+            let now = now_ms();
+            let mut conn = self.conn();
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+            let current = read(&tx, key, now).map_err(db_error)?;
+            if let Some((value, ttl)) = update(current.as_deref()) {
+                write(&tx, key, &value, ttl, now).map_err(db_error)?;
+            }
+            tx.commit().map_err(db_error)?;
+            self.wrote(&conn, now)
+        }
+
+        fn update_many(
+            &self,
+            keys: &[String],
+            update: &mut dyn FnMut(&[Option<String>]) -> Updates,
+        ) -> Result<(), BrightDataError> {
+            let now = now_ms();
+            let mut conn = self.conn();
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+            let current = keys.iter().map(|key| read(&tx, key, now)).collect::<rusqlite::Result<Vec<_>>>().map_err(db_error)?;
+            for (key, change) in keys.iter().zip(update(&current).unwrap_or_default()) {
+                if let Some((value, ttl)) = change {
+                    write(&tx, key, &value, ttl, now).map_err(db_error)?;
+                }
+            }
+            tx.commit().map_err(db_error)?;
+            self.wrote(&conn, now)
+        }
+
+        fn scan(&self, prefix: &str) -> Result<Vec<Entry>, BrightDataError> {
+            let now = now_ms();
+            let conn = self.conn();
+            let mut stmt = conn
+                .prepare(
+                    "SELECT key, value, expires_at FROM state
+                     WHERE substr(key, 1, length(?1)) = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+                )
+                .map_err(db_error)?;
+            let rows = stmt
+                .query_map(params![prefix, now as i64], |row| {
+                    let expires_at: Option<i64> = row.get(2)?;
+                    Ok(Entry {
+                        key: row.get(0)?,
+                        value: row.get(1)?,
+                        ttl: expires_at.map(|at| Duration::from_millis((at as u64).saturating_sub(now))),
+                    })
+                })
+                .map_err(db_error)?;
+            rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+        }
+    }
+}
+
+/// Store shared by the server and the tools, from `STATE_STORE`.
+pub fn global() -> Arc<dyn StateStore> {
+    GLOBAL
+        .get_or_init(|| {
+            StoreConfig::from_env()
+                .map_err(|e| BrightDataError::Unexpected(anyhow::anyhow!(e)))
+                .and_then(|config| config.open())
+                .unwrap_or_else(|e| {
+                    log::error!("Cannot open state store: {}, keeping state in memory", e);
+                    Arc::new(MemoryStore::new())
+                })
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_ttls_never_expire_early() {
+        assert_eq!(expiry(Some(Duration::from_micros(10)), 1_000), Some(1_002));
+        assert_eq!(expiry(Some(Duration::from_millis(5)), 1_000), Some(1_006));
+        assert_eq!(expiry(None, 1_000), None);
+
+        let store = MemoryStore::new();
+        store.set("k", "v", Some(Duration::from_micros(10))).unwrap();
+        assert_eq!(store.get("k").unwrap().as_deref(), Some("v"));
+    }
+
+    /// Behaviour every store must have.
+    fn check_port(store: &dyn StateStore) {
+        store.set("a", "1", None).unwrap();
+        store.set("gone", "x", Some(Duration::from_millis(1))).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+        assert_eq!(store.get("gone").unwrap(), None);
+        assert!(!store.delete("gone").unwrap());
+
+        store.update("a", &mut |current| Some((format!("{}2", current.unwrap_or_default()), None))).unwrap();
+        assert_eq!(store.get("a").unwrap().as_deref(), Some("12"));
+        store.update("a", &mut |_| None).unwrap();
+        assert_eq!(store.get("a").unwrap().as_deref(), Some("12"));
+
+        let keys = ["a".to_string(), "b".to_string()];
+        store
+            .update_many(&keys, &mut |current| {
+                assert_eq!(current, [Some("12".to_string()), None]);
+                None
+            })
+            .unwrap();
+        assert_eq!(store.get("b").unwrap(), None);
+        store
+            .update_many(&keys, &mut |_| Some(vec![None, Some(("3".to_string(), Some(Duration::from_secs(60))))]))
+            .unwrap();
+        assert_eq!(store.get("a").unwrap().as_deref(), Some("12"));
+        assert_eq!(store.get("b").unwrap().as_deref(), Some("3"));
+
+        let mut entries = store.scan("").unwrap();
+        entries.sort_by(|x, y| x.key.cmp(&y.key));
+        assert_eq!(entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(entries[0].ttl, None);
+        assert!(entries[1].ttl.is_some_and(|ttl| ttl > Duration::from_secs(59) && ttl <= Duration::from_secs(61)));
+
+        assert!(store.delete("a").unwrap());
+        assert_eq!(store.get("a").unwrap(), None);
+    }
+
+    #[test]
+    fn memory_store_port() {
+        check_port(&MemoryStore::new());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_store_port() {
+        let path = std::env::temp_dir().join(format!("snm-store-test-{}.db", std::process::id()));
+        check_port(&SqliteStore::open(&path).unwrap());
+
+        // A second handle on the same file sees the first one's writes.
+        let other = SqliteStore::open(&path).unwrap();
+        assert_eq!(other.get("b").unwrap().as_deref(), Some("3"));
+        for file in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), file));
+        }
+    }
+}
@@ -1,9 +1,13 @@
 // src/tools/scrape.rs
 use crate::tool::Tool;
 use crate::error::BrightDataError;
+use crate::detect;
+use crate::hedge;
+use crate::proxy::{ProxyClient, ProxyTarget, Unblock};
+use crate::retry::RetryPolicy;
+use crate::session::{self, StickyRequest};
 use async_trait::async_trait;
 use serde_json::{Value, json};
-use reqwest::Client;
 
 pub struct ScrapeMarkdown;
 
@@ -21,56 +25,48 @@ impl Tool for ScrapeMarkdown {
         let url = parameters
             .get("url")
             .and_then(|v| v.as_str())
-            .ok_or_else(|| BrightDataError::ToolError("Missing 'url'".into()))?;
+            .ok_or_else(|| BrightDataError::InvalidParams("Missing 'url'".into()))?;
 
-        let zone = std::env::var("WEB_UNLOCKER_ZONE")
-            .unwrap_or_else(|_| "snm_rustacean_scraper_unlocker".into());
+        let profile = crate::profile::load(parameters.get("profile").and_then(|v| v.as_str()))?;
 
-        let client = Client::new();
+        let proxy = ProxyClient::from_env()?;
+        let detector = detect::global();
+        let retry_policy = RetryPolicy::from_env().for_call(&parameters);
+        let sessions = session::global();
+        let sticky = StickyRequest::from_args(&parameters);
+        let mut target = ProxyTarget::zone(profile.web_unlocker_zone);
+        // Other calls go without a session and share one pooled client; only
+        // rotations and hedges need a fresh one.
+        target.session = match &sticky {
+            Some(req) => Some(sessions.session_for(req).await),
+            None => None,
+        };
+        let target = target.with_arguments(&parameters);
+        let rotate = parameters.get("session").is_none();
+        let hedger = hedge::global();
+        let unblock = Unblock {
+            hedger: &hedger,
+            // Hedges run on a fresh session, so calls pinned to one are not hedged.
+            hedge: hedger.config().enabled_for(&parameters) && rotate && sticky.is_none(),
+            detector: &detector,
+            // A raw session id chosen by the caller is kept even when it gets blocked.
+            rotate,
+            sticky: sticky.as_ref().map(|req| (&*sessions, req)),
+        };
+        let page = proxy
+            .fetch_unblocked(target, url, &retry_policy, "scrape_website", unblock, || async { Ok(()) })
+            .await?;
 
-        let proxy_username = std::env::var("BRIGHTDATA_PROXY_USERNAME").unwrap_or_default();
-        let proxy_password = std::env::var("BRIGHTDATA_PROXY_PASSWORD").unwrap_or_default();
-        let session_id = uuid::Uuid::new_v4().to_string();
-
-        let full_url = format!(
-            "http://{}-session-{}:{}@{}:{}/scrape",
-            proxy_username,
-            session_id,
-            proxy_password,
-            std::env::var("BRIGHTDATA_PROXY_HOST").unwrap_or_else(|_| "zproxy.lum-superproxy.io".into()),
-            std::env::var("BRIGHTDATA_PROXY_PORT").unwrap_or_else(|_| "22225".into()),
-        );
-
-        let payload = json!({
-            "url": url,
-            "zone": zone,
-            "render": false,
-            "markdown": true
-        });
-
-        let res = client
-            .post(&full_url)
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| BrightDataError::ToolError(format!("HTTP error: {}", e)))?;
-
-        let status = res.status();
-
-        if !status.is_success() {
-            let err = res.text().await.unwrap_or_default();
-            return Err(BrightDataError::ToolError(format!(
-                "BrightData scrape error ({}): {}",
-                status,
-                err
-            )));
+        if let Some(req) = &sticky {
+            sessions.finish(req).await;
         }
 
-
-        let data: Value = res.json().await.map_err(|e| {
-            BrightDataError::ToolError(format!("Failed to parse response: {}", e))
-        })?;
-
-        Ok(data)
+        Ok(json!({
+            "content": page.body,
+            "attempts": page.attempts,
+            "rotations": page.rotations,
+            "session": page.session,
+            "hedged": page.hedged,
+        }))
     }
 }
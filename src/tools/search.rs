@@ -1,9 +1,12 @@
 // src/tools/search.rs
 use crate::tool::Tool;
-use crate::error::BrightDataError;
+use crate::error::{BrightDataError, ErrorContext};
+use crate::client::BrightDataClient;
+use crate::detect;
+use crate::request::{self, SearchRequest};
+use crate::retry::{Retried, RetryPolicy};
 use async_trait::async_trait;
 use serde_json::{json, Value};
-use reqwest::Client;
 
 pub struct SearchEngine;
 
@@ -21,59 +24,32 @@ impl Tool for SearchEngine {
         let query = parameters
             .get("query")
             .and_then(|v| v.as_str())
-            .ok_or_else(|| BrightDataError::ToolError("Missing 'query'".into()))?;
+            .ok_or_else(|| BrightDataError::InvalidParams("Missing 'query'".into()))?;
 
-        let engine = parameters.get("engine").and_then(|v| v.as_str()).unwrap_or("google");
-        let cursor = parameters.get("cursor").and_then(|v| v.as_str()).unwrap_or("0");
+        let search = SearchRequest {
+            query: query.to_string(),
+            engine: parameters.get("engine").and_then(|v| v.as_str()).map(String::from),
+            cursor: parameters.get("cursor").and_then(|v| v.as_str()).map(String::from),
+        };
 
-        let url = build_search_url(engine, query, cursor);
+        let profile = crate::profile::load(parameters.get("profile").and_then(|v| v.as_str()))?;
 
-        let body = json!({
-            "url": url,
-            "zone": std::env::var("WEB_UNLOCKER_ZONE").unwrap_or_else(|_| "default".into()),
-            "format": "raw",
-            "data_format": "markdown"
-        });
+        let body = request::search_payload(&profile, &search);
 
-        let api_token = std::env::var("BRIGHTDATA_API_TOKEN")
-            .map_err(|_| BrightDataError::ToolError("Missing BRIGHTDATA_API_TOKEN".into()))?;
-
-        let client = Client::new();
-
-        let response = client
-            .post("https://api.brightdata.com/request")
-            .header("Authorization", format!("Bearer {}", api_token))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| BrightDataError::ToolError(format!("Request failed: {}", e)))?;
-
-        let status = response.status();
-        let text = response
-            .text()
-            .await
-            .map_err(|e| BrightDataError::ToolError(format!("Invalid response: {}", e)))?;
-
-        if !status.is_success() {
-            return Err(BrightDataError::ToolError(format!(
-                "BrightData error {}: {}",
-                status, text
-            )));
+        if profile.token.is_empty() {
+            return Err(BrightDataError::Auth(ErrorContext {
+                message: "Missing BRIGHTDATA_API_TOKEN".into(),
+                ..Default::default()
+            }));
         }
 
-        Ok(json!({ "raw": text }))
-    }
-}
-
-fn build_search_url(engine: &str, query: &str, cursor: &str) -> String {
-    let encoded = urlencoding::encode(query);
-    let page: usize = cursor.parse().unwrap_or(0);
-    let start = page * 10;
+        let retry_policy = RetryPolicy::from_env().for_call(&parameters);
+        let Retried { value: text, attempts } = BrightDataClient::new(profile)
+            .with_retry_policy(retry_policy)
+            .post("search_web", &body)
+            .await?;
+        detect::global().check(body["url"].as_str().unwrap_or_default(), &text, body["zone"].as_str())?;
 
-    match engine {
-        "bing" => format!("https://www.bing.com/search?q={}&first={}", encoded, start + 1),
-        "yandex" => format!("https://yandex.com/search/?text={}&p={}", encoded, page),
-        "duckduckgo" => format!("https://duckduckgo.com/?q={}&s={}", encoded, start),
-        _ => format!("https://www.google.com/search?q={}&start={}", encoded, start),
+        Ok(json!({ "raw": text, "attempts": attempts }))
     }
 }
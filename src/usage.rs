@@ -0,0 +1,221 @@
+// src/usage.rs
+//! Usage ledger: one record per tool call.
+//!
+//! Records are appended as JSON lines to the file named by `USAGE_LEDGER`,
+//! so they survive restarts and can be reconciled against the Bright Data
+//! dashboard. Without it the most recent calls are kept in memory only.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Records kept when there is no ledger file.
+const MAX_IN_MEMORY: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    pub client: String,
+    pub tool: String,
+    pub zone: Option<String>,
+    pub success: bool,
+    /// Error kind of a failed call.
+    pub error: Option<String>,
+    pub attempts: u32,
+    pub bytes: u64,
+    /// Estimated cost in USD.
+    pub cost: f64,
+    pub latency_ms: u64,
+}
+
+impl UsageRecord {
+    pub const CSV_HEADER: &'static str = "timestamp,client,tool,zone,success,error,attempts,bytes,cost,latency_ms";
+
+    pub fn to_csv(&self) -> String {
+        [
+            self.timestamp.to_rfc3339(),
+            csv_field(&self.client),
+            csv_field(&self.tool),
+            csv_field(self.zone.as_deref().unwrap_or_default()),
+            self.success.to_string(),
+            csv_field(self.error.as_deref().unwrap_or_default()),
+            self.attempts.to_string(),
+            self.bytes.to_string(),
+            format!("{:.6}", self.cost),
+            self.latency_ms.to_string(),
+        ]
+        .join(",")
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Which records a query covers. `to` is exclusive.
+#[derive(Debug, Clone, Default)]
+pub struct UsageFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub client: Option<String>,
+    pub tool: Option<String>,
+}
+
+impl UsageFilter {
+    pub fn matches(&self, record: &UsageRecord) -> bool {
+        self.from.is_none_or(|from| record.timestamp >= from)
+            && self.to.is_none_or(|to| record.timestamp < to)
+            && self.client.as_ref().is_none_or(|c| &record.client == c)
+            && self.tool.as_ref().is_none_or(|t| &record.tool == t)
+    }
+}
+
+/// Parse an RFC 3339 time or a `YYYY-MM-DD` date. With `end_of_day` a date
+/// means the following midnight, so `to=2024-05-01` includes that day.
+pub fn parse_time(text: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid time '{}', expected RFC 3339 or YYYY-MM-DD", text))?;
+    let date = if end_of_day { date.succ_opt().unwrap_or(date) } else { date };
+    Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+}
+
+/// Totals for one client, tool and zone.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    pub calls: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub bytes: u64,
+    pub cost: f64,
+    pub avg_latency_ms: u64,
+    #[serde(skip)]
+    total_latency_ms: u64,
+}
+
+impl UsageSummary {
+    fn add(&mut self, record: &UsageRecord) {
+        self.calls += 1;
+        if record.success {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.bytes += record.bytes;
+        self.cost += record.cost;
+        self.total_latency_ms += record.latency_ms;
+        self.avg_latency_ms = self.total_latency_ms / self.calls;
+    }
+}
+
+/// Overall totals plus one row per client, tool and zone.
+pub fn summarize(records: &[UsageRecord]) -> (UsageSummary, Vec<UsageSummary>) {
+    let mut total = UsageSummary::default();
+    let mut groups: BTreeMap<(String, String, Option<String>), UsageSummary> = BTreeMap::new();
+    for record in records {
+        total.add(record);
+        groups
+            .entry((record.client.clone(), record.tool.clone(), record.zone.clone()))
+            .or_insert_with(|| UsageSummary {
+                client: Some(record.client.clone()),
+                tool: Some(record.tool.clone()),
+                zone: record.zone.clone(),
+                ..Default::default()
+            })
+            .add(record);
+    }
+    (total, groups.into_values().collect())
+}
+
+#[derive(Debug)]
+enum Store {
+    File { path: PathBuf, file: File },
+    Memory(VecDeque<UsageRecord>),
+}
+
+#[derive(Debug)]
+pub struct UsageLedger {
+    store: Mutex<Store>,
+}
+
+impl UsageLedger {
+    pub fn in_memory() -> Self {
+        Self { store: Mutex::new(Store::Memory(VecDeque::new())) }
+    }
+
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { store: Mutex::new(Store::File { path, file }) })
+    }
+
+    /// `USAGE_LEDGER` if set, otherwise in memory.
+    pub fn from_env() -> io::Result<Self> {
+        match std::env::var("USAGE_LEDGER") {
+            Ok(path) if !path.is_empty() => Self::open(path),
+            _ => Ok(Self::in_memory()),
+        }
+    }
+
+    pub fn record(&self, record: UsageRecord) {
+        let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        match &mut *store {
+            Store::File { path, file } => {
+                // One write per record so concurrent appenders never interleave lines.
+                let written = serde_json::to_string(&record)
+                    .map_err(io::Error::from)
+                    .and_then(|line| file.write_all(format!("{}\n", line).as_bytes()));
+                if let Err(e) = written {
+                    log::warn!("Failed to append to usage ledger {}: {}", path.display(), e);
+                }
+            }
+            Store::Memory(records) => {
+                if records.len() == MAX_IN_MEMORY {
+                    records.pop_front();
+                }
+                records.push_back(record);
+            }
+        }
+    }
+
+    /// Records matching `filter`, oldest first.
+    pub fn query(&self, filter: &UsageFilter) -> io::Result<Vec<UsageRecord>> {
+        let path = {
+            let store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+            match &*store {
+                Store::File { path, .. } => path.clone(),
+                Store::Memory(records) => return Ok(records.iter().filter(|r| filter.matches(r)).cloned().collect()),
+            }
+        };
+
+        let mut records = Vec::new();
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            // A line cut short by a crash is skipped rather than failing the query.
+            match serde_json::from_str::<UsageRecord>(&line) {
+                Ok(record) if filter.matches(&record) => records.push(record),
+                Ok(_) => {}
+                Err(e) => log::debug!("Skipping bad usage ledger line: {}", e),
+            }
+        }
+        Ok(records)
+    }
+}